@@ -5,6 +5,8 @@ export class Razel {
     private static _instance: Razel;
     static readonly outDir = 'razel-out';
     private commands: Command[] = [];
+    private pools: Pool[] = [];
+    private lanes: Lane[] = [];
 
     private constructor(public readonly workspaceDir: string) {
     }
@@ -39,6 +41,18 @@ export class Razel {
         return this.add(command) as Task;
     }
 
+    // limit concurrently running commands, commands join the pool by name or by executable
+    addPool(name: string, depth: number, executables: string[] = []): string {
+        this.pools.push({pool: name, depth, executables});
+        return name;
+    }
+
+    // declare a lane or change its limit, commands in a lane do not use CPU slots
+    setLaneLimit(name: string, limit: number): string {
+        this.lanes.push({lane: name, limit});
+        return name;
+    }
+
     ensureEqual(file1: File, file2: File) {
         const name = `${file1.basename}##shouldEqual##${file2.basename}`;
         this.add(new Task(name, 'ensure-equal', [file1, file2]));
@@ -50,7 +64,11 @@ export class Razel {
     }
 
     writeRazelFile() {
-        const json = this.commands.map(x => JSON.stringify(x.json()));
+        const json = [
+            ...this.pools.map(x => JSON.stringify(x)),
+            ...this.lanes.map(x => JSON.stringify(x)),
+            ...this.commands.map(x => JSON.stringify(x.json())),
+        ];
         Deno.writeTextFileSync(`${this.workspaceDir}/razel.jsonl`, json.join('\n'));
     }
 
@@ -107,13 +125,77 @@ export abstract class Command {
     abstract json(): any;
 }
 
+export interface ResourceRequest {
+    cpus?: number;
+    // peak memory in MiB
+    memory?: number;
+    exclusive?: boolean;
+    pool?: string;
+    // e.g. 'network' for commands which do not use CPU slots
+    lane?: string;
+    // resources used exclusively, e.g. a database used by tests
+    locks?: string[];
+}
+
+// rules to decide if a command succeeded, by default the exit code must be 0
+export interface SuccessCriteria {
+    // exit codes to accept instead of 0
+    expected_exit_codes?: number[];
+    // the command is expected to fail
+    will_fail?: boolean;
+    // regexes for the combined stdout/stderr, a pass regex must match and fail regexes must not match
+    pass_regex?: string[];
+    fail_regex?: string[];
+}
+
+// quoting of args within a response file
+export type ResponseFileStyle = 'gcc' | 'msvc' | 'one-per-line';
+
+interface ResponseFile {
+    first_arg: number;
+    style: ResponseFileStyle;
+}
+
+interface Pool {
+    pool: string;
+    depth: number;
+    executables: string[];
+}
+
+interface Lane {
+    lane: string;
+    limit: number;
+}
+
 export class CustomCommand extends Command {
+    private resources: ResourceRequest = {};
+    private successCriteria: SuccessCriteria = {};
+    private responseFile?: ResponseFile;
+
     constructor(name: string, public readonly executable: string, public readonly args: (string | File)[],
                 public readonly env?: any) {
         super(name, args.filter(x => (x instanceof File) && !(x as File).isData && !(x as File).createdBy) as File[]);
         this.outputs.forEach(x => x.createdBy = this);
     }
 
+    // reserve resources for executing the command, defaults to 1 cpu
+    requireResources(resources: ResourceRequest): CustomCommand {
+        this.resources = resources;
+        return this;
+    }
+
+    // pass args starting at firstArg in a response file written by razel, e.g. to not exceed ARG_MAX
+    useResponseFile(firstArg: number, style: ResponseFileStyle = 'gcc'): CustomCommand {
+        this.responseFile = {first_arg: firstArg, style};
+        return this;
+    }
+
+    // decide by exit codes and output instead of only by a zero exit code if the command succeeded
+    setSuccessCriteria(criteria: SuccessCriteria): CustomCommand {
+        this.successCriteria = criteria;
+        return this;
+    }
+
     commandLine(): string {
         return [
             `./${this.executable}`,
@@ -129,6 +211,9 @@ export class CustomCommand extends Command {
             inputs: this.args.filter(x => x instanceof File && x.createdBy !== this).map(x => (x as File).fileName),
             outputs: this.outputs.map(x => x.fileName),
             env: this.env,
+            ...this.resources,
+            ...this.successCriteria,
+            response_file: this.responseFile,
         };
     }
 }
@@ -2,9 +2,10 @@ use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
+use clap::ArgEnum;
 use sha2::Sha256;
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, BufReader};
+use tokio::io::AsyncReadExt;
 
 use crate::bazel_remote_exec::{ActionResult, Digest, OutputFile};
 use crate::cache::LocalCache;
@@ -12,13 +13,16 @@ use crate::{bazel_remote_exec, force_symlink};
 
 #[derive(Clone)]
 pub struct Cache {
+    pub digest_function: DigestFunction,
     local_cache: LocalCache,
 }
 
 impl Cache {
-    pub fn new() -> Result<Self, anyhow::Error> {
+    pub fn new(digest_function: DigestFunction) -> Result<Self, anyhow::Error> {
         Ok(Self {
-            local_cache: LocalCache::new().with_context(|| "Failed to create local cache")?,
+            digest_function,
+            local_cache: LocalCache::new(digest_function)
+                .with_context(|| "Failed to create local cache")?,
         })
     }
 
@@ -49,16 +53,26 @@ impl Cache {
             .as_ref()
             .map_or(exec_path.clone(), |x| x.join(exec_path));
         assert!(!src.is_symlink(), "src must not be a symlink: {:?}", src);
-        let digest = Digest::for_file(&src).await?;
+        let digest = Digest::for_file(&src, self.digest_function).await?;
         let dst = self.local_cache.cas_dir.join(&digest.hash);
         let path: String = exec_path.strip_prefix(&out_dir).map_or_else(
             |_| exec_path.to_str().unwrap().into(),
             |x| x.to_str().unwrap().into(),
         );
         assert!(Path::new(&path).is_relative());
-        tokio::fs::rename(&src, &dst)
-            .await
-            .with_context(|| format!("mv {:?} -> {:?}", src, dst))?;
+        // blobs are linked into sandboxes and must not be modified by commands
+        let mut permissions = tokio::fs::metadata(&src).await?.permissions();
+        permissions.set_readonly(true);
+        tokio::fs::set_permissions(&src, permissions).await?;
+        if tokio::fs::rename(&src, &dst).await.is_err() {
+            // sandbox dir might be on another device, e.g. tmpfs
+            if !dst.exists() {
+                tokio::fs::copy(&src, &dst)
+                    .await
+                    .with_context(|| format!("mv {:?} -> {:?}", src, dst))?;
+            }
+            tokio::fs::remove_file(&src).await?;
+        }
         Ok(OutputFile {
             path,
             digest: Some(digest),
@@ -105,18 +119,52 @@ pub trait ContentAddressableStorage {
 pub type MessageDigest = Digest;
 pub type BlobDigest = Digest;
 
+/// Hash function used for digests of blobs and messages, see REAPI DigestFunction
+#[derive(ArgEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DigestFunction {
+    #[default]
+    Sha256,
+    /// Faster than SHA-256, large files are hashed multithreaded
+    Blake3,
+}
+
+impl DigestFunction {
+    /// Name used for the local cache dir
+    pub fn name(&self) -> &'static str {
+        match self {
+            DigestFunction::Sha256 => "sha256",
+            DigestFunction::Blake3 => "blake3",
+        }
+    }
+}
+
+/// Files larger than this are hashed with BLAKE3 using multiple threads
+static BLAKE3_MULTITHREADED_MIN_SIZE: u64 = 1024 * 1024;
+
 impl Digest {
-    pub async fn for_file(path: impl AsRef<Path> + Debug) -> Result<BlobDigest, anyhow::Error> {
+    pub async fn for_file(
+        path: impl AsRef<Path> + Debug,
+        digest_function: DigestFunction,
+    ) -> Result<BlobDigest, anyhow::Error> {
+        match digest_function {
+            DigestFunction::Sha256 => Self::for_file_sha256(path).await,
+            DigestFunction::Blake3 => {
+                let path = path.as_ref().to_path_buf();
+                tokio::task::spawn_blocking(move || Self::for_file_blake3(&path)).await?
+            }
+        }
+    }
+
+    async fn for_file_sha256(path: impl AsRef<Path> + Debug) -> Result<BlobDigest, anyhow::Error> {
         use sha2::Digest;
-        let file = File::open(&path)
+        let mut file = File::open(&path)
             .await
             .with_context(|| format!("Failed to open {:?}", path))?;
-        let mut reader = BufReader::new(file);
         let mut hasher = Sha256::new();
-        let mut buffer = [0; 1024];
+        let mut buffer = vec![0; 64 * 1024];
         let mut len = 0;
         loop {
-            let count = reader
+            let count = file
                 .read(&mut buffer)
                 .await
                 .with_context(|| format!("Failed to read {:?}", path))?;
@@ -132,15 +180,43 @@ impl Digest {
         })
     }
 
-    pub fn for_message<T: prost::Message>(msg: &T) -> MessageDigest {
-        use sha2::Digest;
+    /// Blocking, uses memory mapping and the rayon thread pool for large files
+    fn for_file_blake3(path: &Path) -> Result<BlobDigest, anyhow::Error> {
+        let len = std::fs::metadata(path)
+            .with_context(|| format!("Failed to open {:?}", path))?
+            .len();
+        let mut hasher = blake3::Hasher::new();
+        if len >= BLAKE3_MULTITHREADED_MIN_SIZE {
+            hasher.update_mmap_rayon(path)
+        } else {
+            hasher.update_mmap(path)
+        }
+        .with_context(|| format!("Failed to read {:?}", path))?;
+        Ok(bazel_remote_exec::Digest {
+            hash: hasher.finalize().to_hex().to_string(),
+            size_bytes: hasher.count() as i64,
+        })
+    }
+
+    pub fn for_message<T: prost::Message>(
+        msg: &T,
+        digest_function: DigestFunction,
+    ) -> MessageDigest {
         let buf = message_to_pb_buf(msg);
         bazel_remote_exec::Digest {
-            hash: Self::hex(&Sha256::digest(&buf)),
+            hash: Self::for_bytes(&buf, digest_function),
             size_bytes: buf.len() as i64,
         }
     }
 
+    fn for_bytes(bytes: &[u8], digest_function: DigestFunction) -> String {
+        use sha2::Digest;
+        match digest_function {
+            DigestFunction::Sha256 => Self::hex(&Sha256::digest(bytes)),
+            DigestFunction::Blake3 => blake3::hash(bytes).to_hex().to_string(),
+        }
+    }
+
     fn hex(input: &[u8]) -> String {
         base16ct::lower::encode_string(input)
     }
@@ -170,7 +246,9 @@ mod tests {
     #[tokio::test]
     async fn small_file() {
         let path = "test/data/a.csv";
-        let act = super::Digest::for_file(&path).await.unwrap();
+        let act = super::Digest::for_file(&path, DigestFunction::Sha256)
+            .await
+            .unwrap();
         let exp = digest_file_sha256_simple(&path).unwrap();
         assert_eq!(act, exp);
         assert_eq!(
@@ -185,8 +263,36 @@ mod tests {
     #[tokio::test]
     async fn bigger_file() {
         let path = "Cargo.lock";
-        let act = super::Digest::for_file(&path).await.unwrap();
+        let act = super::Digest::for_file(&path, DigestFunction::Sha256)
+            .await
+            .unwrap();
         let exp = digest_file_sha256_simple(&path).unwrap();
         assert_eq!(act, exp);
     }
+
+    #[tokio::test]
+    async fn blake3() {
+        let path = "test/data/a.csv";
+        let act = super::Digest::for_file(&path, DigestFunction::Blake3)
+            .await
+            .unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(act.hash, blake3::hash(&bytes).to_hex().to_string());
+        assert_eq!(act.size_bytes, 16);
+    }
+
+    #[tokio::test]
+    async fn blake3_multithreaded() {
+        let dir = temp_dir::TempDir::new().unwrap();
+        let path = dir.child("big");
+        let bytes = (0..BLAKE3_MULTITHREADED_MIN_SIZE * 3)
+            .map(|x| x as u8)
+            .collect::<Vec<_>>();
+        std::fs::write(&path, &bytes).unwrap();
+        let act = super::Digest::for_file(&path, DigestFunction::Blake3)
+            .await
+            .unwrap();
+        assert_eq!(act.hash, blake3::hash(&bytes).to_hex().to_string());
+        assert_eq!(act.size_bytes, bytes.len() as i64);
+    }
 }
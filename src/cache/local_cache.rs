@@ -8,7 +8,7 @@ use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 
 use crate::bazel_remote_exec::{ActionResult, Digest};
-use crate::cache::{message_to_pb_buf, MessageDigest};
+use crate::cache::{message_to_pb_buf, DigestFunction, MessageDigest};
 use crate::config;
 
 #[derive(Clone)]
@@ -19,8 +19,10 @@ pub struct LocalCache {
 }
 
 impl LocalCache {
-    pub fn new() -> Result<Self, anyhow::Error> {
-        let dir = Self::dir();
+    /// Entries of different digest functions are kept in separate dirs
+    pub fn new(digest_function: DigestFunction) -> Result<Self, anyhow::Error> {
+        Self::migrate_legacy_dirs();
+        let dir = Self::dir_for_digest_function(digest_function);
         let ac_dir = dir.join("ac");
         let cas_dir = dir.join("cas");
         std::fs::create_dir_all(&ac_dir)?;
@@ -33,6 +35,31 @@ impl LocalCache {
         project_dirs.cache_dir().into()
     }
 
+    pub fn dir_for_digest_function(digest_function: DigestFunction) -> PathBuf {
+        Self::dir().join(digest_function.name())
+    }
+
+    /// Move the `ac` and `cas` dirs of older versions, which were kept directly in the cache dir and
+    /// always used SHA-256, into the dir of that digest function, remove them if it has those already.
+    fn migrate_legacy_dirs() {
+        let dir = Self::dir();
+        let sha256_dir = Self::dir_for_digest_function(DigestFunction::Sha256);
+        for name in ["ac", "cas"] {
+            let legacy_dir = dir.join(name);
+            if !legacy_dir.is_dir() {
+                continue;
+            }
+            let new_dir = sha256_dir.join(name);
+            if new_dir.exists() {
+                std::fs::remove_dir_all(&legacy_dir).ok();
+            } else if let Err(x) = std::fs::create_dir_all(&sha256_dir)
+                .and_then(|_| std::fs::rename(&legacy_dir, &new_dir))
+            {
+                warn!("Failed to migrate local cache dir {:?}: {}", legacy_dir, x);
+            }
+        }
+    }
+
     pub async fn get_action_result(&self, digest: &MessageDigest) -> Option<ActionResult> {
         let path = self.ac_dir.join(&digest.hash);
         match Self::try_read_pb_file(&path).await {
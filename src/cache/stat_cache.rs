@@ -0,0 +1,330 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::bazel_remote_exec::Digest;
+use crate::cache::{BlobDigest, DigestFunction, LocalCache};
+
+/// Files modified within this duration are always hashed and their digests are not cached,
+/// because a further modification might not change the mtime, e.g. on file systems with coarse
+/// timestamp granularity
+static RACY_DURATION: Duration = Duration::from_secs(1);
+
+/// Metadata used to detect modifications of a file without reading it
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FileState {
+    pub size: u64,
+    /// nanoseconds since UNIX epoch
+    pub mtime: i64,
+    /// nanoseconds since UNIX epoch, 0 if not supported
+    pub ctime: i64,
+    /// 0 if not supported
+    pub inode: u64,
+}
+
+impl FileState {
+    pub fn new(metadata: &fs::Metadata) -> Self {
+        let mtime = metadata.modified().map_or(0, |x| system_time_to_nanos(&x));
+        #[cfg(unix)]
+        let (ctime, inode) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                metadata.ctime() * 1_000_000_000 + metadata.ctime_nsec(),
+                metadata.ino(),
+            )
+        };
+        #[cfg(not(unix))]
+        let (ctime, inode) = (0, 0);
+        Self {
+            size: metadata.len(),
+            mtime,
+            ctime,
+            inode,
+        }
+    }
+
+    /// Returns true if the file was modified within RACY_DURATION before now.
+    ///
+    /// The ctime is checked too, because the content might be changed while keeping the mtime,
+    /// e.g. by `cp -p` or `touch -r`.
+    fn is_racy(&self, now: &SystemTime) -> bool {
+        system_time_to_nanos(now) - self.mtime.max(self.ctime) < RACY_DURATION.as_nanos() as i64
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StatCacheEntry {
+    #[serde(flatten)]
+    pub state: FileState,
+    pub hash: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct StatCacheJson {
+    path: PathBuf,
+    #[serde(flatten)]
+    entry: StatCacheEntry,
+}
+
+/// Persistent cache of input file digests, keyed by absolute path.
+///
+/// A digest is reused if size, mtime, ctime and inode of the file are unchanged.
+/// Entries of files which are not used in a run are removed when saving.
+#[derive(Debug, Default)]
+pub struct StatCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, StatCacheEntry>,
+    /// paths looked up or inserted in this run
+    used: HashSet<PathBuf>,
+    changed: bool,
+}
+
+impl StatCache {
+    pub fn default_path(digest_function: DigestFunction) -> PathBuf {
+        LocalCache::dir_for_digest_function(digest_function).join("stat_cache.jsonl")
+    }
+
+    /// Read the cache, invalid entries are ignored
+    pub fn load(path: &Path) -> Self {
+        let mut cache = Self {
+            path: path.into(),
+            entries: Default::default(),
+            used: Default::default(),
+            changed: false,
+        };
+        let file = match fs::File::open(path) {
+            Ok(x) => x,
+            Err(_) => return cache,
+        };
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            match serde_json::from_str::<StatCacheJson>(&line) {
+                Ok(x) => {
+                    cache.entries.insert(x.path, x.entry);
+                }
+                Err(e) => warn!("invalid entry in {:?}: {}", path, e),
+            }
+        }
+        cache
+    }
+
+    pub fn get(&mut self, path: &Path) -> Option<&StatCacheEntry> {
+        self.used.insert(path.into());
+        self.entries.get(path)
+    }
+
+    pub fn insert(&mut self, path: PathBuf, entry: StatCacheEntry) {
+        self.used.insert(path.clone());
+        if self.entries.get(&path) != Some(&entry) {
+            self.entries.insert(path, entry);
+            self.changed = true;
+        }
+    }
+
+    /// Digest a file, reusing the cached digest if the file is unchanged.
+    ///
+    /// Returns the entry to be cached, None if the file was modified recently.
+    pub async fn digest_file(
+        path: PathBuf,
+        cached: Option<StatCacheEntry>,
+        digest_function: DigestFunction,
+    ) -> Result<(BlobDigest, Option<StatCacheEntry>), anyhow::Error> {
+        Self::digest_file_at(path, cached, digest_function, SystemTime::now()).await
+    }
+
+    async fn digest_file_at(
+        path: PathBuf,
+        cached: Option<StatCacheEntry>,
+        digest_function: DigestFunction,
+        now: SystemTime,
+    ) -> Result<(BlobDigest, Option<StatCacheEntry>), anyhow::Error> {
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .with_context(|| format!("Failed to stat {:?}", path))?;
+        let state = FileState::new(&metadata);
+        if state.is_racy(&now) {
+            return Ok((Digest::for_file(&path, digest_function).await?, None));
+        }
+        if let Some(cached) = cached.filter(|x| x.state == state) {
+            let digest = Digest {
+                hash: cached.hash.clone(),
+                size_bytes: state.size as i64,
+            };
+            return Ok((digest, Some(cached)));
+        }
+        let digest = Digest::for_file(&path, digest_function).await?;
+        let entry = StatCacheEntry {
+            state,
+            hash: digest.hash.clone(),
+        };
+        Ok((digest, Some(entry)))
+    }
+
+    /// Remove entries of files which were not used and write the cache atomically if it was changed
+    pub fn save(&mut self) -> Result<(), anyhow::Error> {
+        let len = self.entries.len();
+        self.entries.retain(|path, _| self.used.contains(path));
+        if self.entries.len() != len {
+            self.changed = true;
+        }
+        if !self.changed {
+            return Ok(());
+        }
+        let tmp_path = self
+            .path
+            .with_extension(format!("{}.tmp", std::process::id()));
+        let mut file = std::io::BufWriter::new(
+            fs::File::create(&tmp_path).with_context(|| format!("{:?}", tmp_path))?,
+        );
+        for (path, entry) in &self.entries {
+            let json = StatCacheJson {
+                path: path.clone(),
+                entry: entry.clone(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&json)?)?;
+        }
+        drop(file);
+        fs::rename(&tmp_path, &self.path).with_context(|| format!("{:?}", self.path))?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+fn system_time_to_nanos(time: &SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(x) => x.as_nanos() as i64,
+        Err(e) => -(e.duration().as_nanos() as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    /// Digest as if some time passed since the file was written, to avoid the racy check
+    async fn digest_later(
+        path: &Path,
+        cached: Option<StatCacheEntry>,
+    ) -> (BlobDigest, Option<StatCacheEntry>) {
+        let later = SystemTime::now() + Duration::from_secs(10);
+        StatCache::digest_file_at(path.into(), cached, DigestFunction::Sha256, later)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reuse_unchanged_file() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.child("a.txt");
+        fs::write(&file, "a").unwrap();
+        let (digest, entry) = digest_later(&file, None).await;
+        let mut entry = entry.unwrap();
+        assert_eq!(
+            digest,
+            Digest::for_file(&file, DigestFunction::Sha256)
+                .await
+                .unwrap()
+        );
+        // a manipulated hash shows that the cached digest is used
+        entry.hash = "cached".into();
+        let (digest, _) = digest_later(&file, Some(entry.clone())).await;
+        assert_eq!(digest.hash, "cached");
+        fs::write(&file, "b").unwrap();
+        let (digest, _) = digest_later(&file, Some(entry)).await;
+        assert_eq!(
+            digest,
+            Digest::for_file(&file, DigestFunction::Sha256)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn rehash_recently_modified_file() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.child("a.txt");
+        fs::write(&file, "a").unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+        let cached = StatCacheEntry {
+            state: FileState::new(&metadata),
+            hash: "cached".into(),
+        };
+        let (digest, entry) =
+            StatCache::digest_file(file.clone(), Some(cached), DigestFunction::Sha256)
+                .await
+                .unwrap();
+        assert_eq!(
+            digest,
+            Digest::for_file(&file, DigestFunction::Sha256)
+                .await
+                .unwrap()
+        );
+        assert_eq!(entry, None);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.child("stat_cache.jsonl");
+        let mut cache = StatCache::load(&path);
+        let entry = StatCacheEntry {
+            state: FileState {
+                size: 1,
+                mtime: 2,
+                ctime: 3,
+                inode: 4,
+            },
+            hash: "abc".into(),
+        };
+        cache.insert("/a".into(), entry.clone());
+        cache.save().unwrap();
+        let mut cache = StatCache::load(&path);
+        assert_eq!(cache.get(Path::new("/a")), Some(&entry));
+        assert_eq!(cache.get(Path::new("/b")), None);
+    }
+
+    #[test]
+    fn racy_ctime() {
+        let now = SystemTime::now();
+        let state = FileState {
+            size: 1,
+            mtime: system_time_to_nanos(&(now - Duration::from_secs(10))),
+            ctime: system_time_to_nanos(&now),
+            inode: 1,
+        };
+        assert!(state.is_racy(&now));
+        assert!(!state.is_racy(&(now + Duration::from_secs(10))));
+    }
+
+    #[test]
+    fn remove_unused_entries() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.child("stat_cache.jsonl");
+        let mut cache = StatCache::load(&path);
+        let entry = StatCacheEntry {
+            state: FileState {
+                size: 1,
+                mtime: 2,
+                ctime: 3,
+                inode: 4,
+            },
+            hash: "abc".into(),
+        };
+        cache.insert("/a".into(), entry.clone());
+        cache.insert("/b".into(), entry.clone());
+        cache.save().unwrap();
+        let mut cache = StatCache::load(&path);
+        assert!(cache.get(Path::new("/a")).is_some());
+        cache.save().unwrap();
+        let mut cache = StatCache::load(&path);
+        assert_eq!(cache.get(Path::new("/a")), Some(&entry));
+        assert_eq!(cache.get(Path::new("/b")), None);
+    }
+}
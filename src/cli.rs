@@ -1,10 +1,15 @@
 use std::error::Error;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use clap::{AppSettings, Args, Parser, Subcommand};
 
-use crate::parse_jsonl::parse_jsonl_file;
-use crate::{parse_batch_file, parse_command, tasks, CommandBuilder, Scheduler};
+use crate::cache::DigestFunction;
+use crate::parse_jsonl::{parse_jsonl_file, parse_pools_file};
+use crate::{
+    parse_batch_file, parse_command, parse_compdb_file, parse_ctest, parse_ninja_file, tasks,
+    CommandBuilder, History, SandboxStrategy, Scheduler,
+};
 
 #[derive(Parser)]
 #[clap(name = "razel")]
@@ -22,6 +27,11 @@ enum CliCommands {
     Command {
         #[clap(last = true, required = true)]
         command: Vec<String>,
+        /// file with rules to detect input/output files of the command, one rule per line
+        #[clap(long)]
+        rules: Option<String>,
+        #[clap(flatten)]
+        run_args: RunArgs,
     },
     /// Execute a single task
     #[clap(subcommand)]
@@ -30,15 +40,110 @@ enum CliCommands {
     Batch {
         /// file with commands to execute
         file: String,
+        /// file with rules to detect input/output files of commands, overrides razel.rules next to the batch file
+        #[clap(long)]
+        rules: Option<String>,
+        #[clap(flatten)]
+        run_args: RunArgs,
     },
     /// Execute commands from a razel.jsonl file
     Build {
         /// file with commands to execute
         #[clap(default_value = "razel.jsonl")]
         file: String,
+        #[clap(flatten)]
+        run_args: RunArgs,
+    },
+    /// Execute commands from a ninja build file
+    Ninja {
+        /// ninja build file, paths are relative to its dir
+        #[clap(default_value = "build.ninja")]
+        file: String,
+        #[clap(flatten)]
+        run_args: RunArgs,
+    },
+    /// Execute compile commands from a JSON Compilation Database
+    ImportCompdb {
+        /// compile_commands.json, e.g. created by CMake with CMAKE_EXPORT_COMPILE_COMMANDS
+        #[clap(default_value = "compile_commands.json")]
+        file: String,
+        #[clap(flatten)]
+        run_args: RunArgs,
+    },
+    /// Execute tests of a CTest build dir
+    Ctest {
+        /// build dir or output of `ctest --show-only=json-v1`
+        build_dir: String,
+        #[clap(flatten)]
+        run_args: RunArgs,
     },
     /// Show info about configuration, cache, ...
     Info,
+    /// Show measured executions of a command
+    History {
+        /// name of the command
+        command: String,
+    },
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Isolate custom commands using Linux namespaces: read-only inputs, private /tmp, hidden workspace
+    #[clap(long)]
+    hermetic: bool,
+    /// Disable network access of custom commands, requires --hermetic
+    #[clap(long, requires = "hermetic")]
+    no_network: bool,
+    /// Fail custom commands which access undeclared inputs or create undeclared outputs (Linux only)
+    #[clap(long)]
+    detect_undeclared: bool,
+    /// How inputs of custom commands are put into the sandbox, can be overridden per command
+    #[clap(long, arg_enum, default_value = "symlink")]
+    sandbox_strategy: SandboxStrategy,
+    /// Directory for temporary sandboxes, defaults to the user runtime or tmp dir
+    #[clap(long)]
+    sandbox_dir: Option<PathBuf>,
+    /// Hash function for digests of files and actions, each function has a separate local cache
+    #[clap(long, arg_enum, default_value = "sha256")]
+    digest_function: DigestFunction,
+    /// jsonl file with pools to limit concurrent commands: {"pool": "name", "depth": 2, "executables": ["tool"]}
+    #[clap(long)]
+    pools: Option<String>,
+    /// Limit of concurrent commands in a lane, e.g. network=8
+    #[clap(long = "lane", parse(try_from_str = parse_key_val), multiple_occurrences(true))]
+    lanes: Vec<(String, usize)>,
+    /// Write a Chrome trace-event file, can be opened in chrome://tracing or Perfetto
+    #[clap(long)]
+    profile: Option<PathBuf>,
+    /// Write build events to a jsonl file while running, e.g. for dashboards
+    #[clap(long)]
+    events: Option<PathBuf>,
+}
+
+impl RunArgs {
+    fn apply(self, scheduler: &mut Scheduler) -> Result<(), anyhow::Error> {
+        scheduler.sandbox_config.hermetic = self.hermetic;
+        scheduler.sandbox_config.network = !self.no_network;
+        scheduler.sandbox_config.detect_undeclared = self.detect_undeclared;
+        scheduler.sandbox_config.strategy = self.sandbox_strategy;
+        if let Some(dir) = self.sandbox_dir {
+            scheduler.sandbox_config.dir = dir;
+        }
+        scheduler.set_digest_function(self.digest_function)?;
+        if let Some(file) = self.pools {
+            parse_pools_file(scheduler, file)?;
+        }
+        for (name, limit) in self.lanes {
+            scheduler.set_lane(name, limit)?;
+        }
+        if let Some(path) = self.profile {
+            scheduler.enable_profile(path);
+        }
+        if let Some(path) = self.events {
+            scheduler.enable_events(&path)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Subcommand)]
@@ -88,9 +193,16 @@ struct CsvFilterTask {
     /// Col names to keep - all other cols are dropped
     #[clap(short, long = "col", multiple_values(true))]
     cols: Vec<String>,
-    /// Fields to keep: Field=Value
-    #[clap(short, long = "field", parse(try_from_str = parse_key_val), multiple_occurrences(true), multiple_values(true))]
-    fields: Vec<(String, String)>,
+    /// Rows to keep: Field=Value, Field!=Value, Field~Regex, Field!~Regex, Field<Number, Field<=Number, ...
+    ///
+    /// Values of the same field given with = and ~ are alternatives, all other conditions must be true.
+    #[clap(
+        short,
+        long = "field",
+        multiple_occurrences(true),
+        multiple_values(true)
+    )]
+    fields: Vec<tasks::CsvFieldFilter>,
 }
 
 impl CsvFilterTask {
@@ -184,14 +296,50 @@ pub fn parse_cli(
 ) -> Result<(), anyhow::Error> {
     let cli = Cli::try_parse_from(args.iter())?;
     match cli.command {
-        CliCommands::Command { command } => parse_command(scheduler, command),
+        CliCommands::Command {
+            command,
+            rules,
+            run_args,
+        } => {
+            run_args.apply(scheduler)?;
+            parse_command(scheduler, command, rules)
+        }
         CliCommands::Task(task) => match_task(scheduler, name.unwrap(), task, args),
-        CliCommands::Batch { file } => parse_batch_file(scheduler, file),
-        CliCommands::Build { file } => parse_jsonl_file(scheduler, file),
+        CliCommands::Batch {
+            file,
+            rules,
+            run_args,
+        } => {
+            run_args.apply(scheduler)?;
+            parse_batch_file(scheduler, file, rules)
+        }
+        CliCommands::Build { file, run_args } => {
+            run_args.apply(scheduler)?;
+            parse_jsonl_file(scheduler, file)
+        }
+        CliCommands::Ninja { file, run_args } => {
+            run_args.apply(scheduler)?;
+            parse_ninja_file(scheduler, file)
+        }
+        CliCommands::ImportCompdb { file, run_args } => {
+            run_args.apply(scheduler)?;
+            parse_compdb_file(scheduler, file)
+        }
+        CliCommands::Ctest {
+            build_dir,
+            run_args,
+        } => {
+            run_args.apply(scheduler)?;
+            parse_ctest(scheduler, build_dir)
+        }
         CliCommands::Info => {
             scheduler.show_info();
             std::process::exit(0);
         }
+        CliCommands::History { command } => {
+            History::load(&History::default_path()).show(&command);
+            std::process::exit(0);
+        }
     }
 }
 
@@ -1,8 +1,16 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::executors::{CustomCommandExecutor, Executor, TaskExecutor, TaskFn};
-use crate::{ArenaId, FileId, ScheduleState, Scheduler};
+use anyhow::bail;
+
+use crate::executors::{
+    AsyncTaskFn, CustomCommandExecutor, Executor, Redirects, ResponseFile, SuccessCriteria,
+    TaskExecutor, TaskFn, TaskFunction,
+};
+use crate::{
+    relative_path, ArenaId, FileId, ResourceRequest, SandboxStrategy, ScheduleState, Scheduler,
+};
 
 pub struct Command {
     pub id: CommandId,
@@ -10,6 +18,13 @@ pub struct Command {
     pub inputs: Vec<FileId>,
     pub outputs: Vec<FileId>,
     pub executor: Executor,
+    /// overrides SandboxConfig::strategy
+    pub sandbox_strategy: Option<SandboxStrategy>,
+    pub resources: ResourceRequest,
+    /// reads files which are not declared as inputs: run without sandbox and not cached
+    pub undeclared_inputs: bool,
+    /// input listing additional inputs, which are added when the command is ready
+    pub deps_file: Option<FileId>,
     /// dependencies which are not yet finished successfully
     pub unfinished_deps: Vec<CommandId>,
     /// commands which depend on this command
@@ -26,7 +41,18 @@ pub struct CommandBuilder {
     args_with_out_paths: Vec<String>,
     inputs: Vec<FileId>,
     outputs: Vec<FileId>,
+    redirects: Redirects,
+    working_dir: Option<PathBuf>,
+    /// prefixes of args with attached paths to map, e.g. `-o` of `-oa.o`
+    arg_prefixes: Vec<String>,
+    timeout: Option<Duration>,
+    success_criteria: SuccessCriteria,
+    response_file: Option<ResponseFile>,
     executor: Option<Executor>,
+    sandbox_strategy: Option<SandboxStrategy>,
+    resources: ResourceRequest,
+    undeclared_inputs: bool,
+    deps_file: Option<FileId>,
 }
 
 impl CommandBuilder {
@@ -37,22 +63,71 @@ impl CommandBuilder {
             args_with_out_paths: args,
             inputs: vec![],
             outputs: vec![],
+            redirects: Default::default(),
+            working_dir: None,
+            arg_prefixes: vec![],
+            timeout: None,
+            success_criteria: Default::default(),
+            response_file: None,
             executor: None,
+            sandbox_strategy: None,
+            resources: Default::default(),
+            undeclared_inputs: false,
+            deps_file: None,
         }
     }
 
-    fn map_exec_path(&mut self, original: &String, mapped: &String) {
-        self.args_with_exec_paths.iter_mut().for_each(|x| {
-            if x == original {
-                *x = mapped.clone()
-            }
-        });
+    /// Run the custom command in a dir relative to cwd.
+    ///
+    /// Must be called before adding files, because their paths are mapped relative to this dir.
+    pub fn working_dir(&mut self, dir: PathBuf) {
+        self.working_dir = Some(dir).filter(|x| !x.as_os_str().is_empty());
+    }
+
+    /// Path of a file relative to cwd as used in the working dir of the command
+    fn path_arg(&self, path: &Path) -> String {
+        match &self.working_dir {
+            Some(x) => relative_path(path, x).to_str().unwrap().into(),
+            None => path.to_str().unwrap().into(),
+        }
+    }
+
+    /// Map paths attached to args with these prefixes, e.g. `-oa.o` or `@args.rsp`.
+    ///
+    /// Must be called before adding files.
+    pub fn arg_prefixes(&mut self, prefixes: Vec<String>) {
+        self.arg_prefixes = prefixes;
+    }
+
+    fn map_exec_path(&mut self, original: &String, mapped: &Path) {
+        let mapped = self.path_arg(mapped);
+        Self::map_args(
+            &mut self.args_with_exec_paths,
+            &self.arg_prefixes,
+            original,
+            &mapped,
+        );
+    }
+
+    fn map_out_path(&mut self, original: &String, mapped: &Path) {
+        let mapped = self.path_arg(mapped);
+        Self::map_args(
+            &mut self.args_with_out_paths,
+            &self.arg_prefixes,
+            original,
+            &mapped,
+        );
     }
 
-    fn map_out_path(&mut self, original: &String, mapped: &String) {
-        self.args_with_out_paths.iter_mut().for_each(|x| {
+    fn map_args(args: &mut [String], prefixes: &[String], original: &str, mapped: &str) {
+        args.iter_mut().for_each(|x| {
             if x == original {
-                *x = mapped.clone()
+                *x = mapped.into();
+            } else if let Some(prefix) = x
+                .strip_suffix(original)
+                .filter(|x| prefixes.iter().any(|p| p == x))
+            {
+                *x = format!("{prefix}{mapped}");
             }
         });
     }
@@ -63,8 +138,8 @@ impl CommandBuilder {
         scheduler: &mut Scheduler,
     ) -> Result<PathBuf, anyhow::Error> {
         scheduler.input_file(path.clone()).map(|file| {
-            self.map_exec_path(path, &file.exec_path.to_str().unwrap().into());
-            self.map_out_path(path, &file.out_path.to_str().unwrap().into());
+            self.map_exec_path(path, &file.exec_path);
+            self.map_out_path(path, &file.out_path);
             self.inputs.push(file.id);
             file.out_path.clone()
         })
@@ -80,8 +155,8 @@ impl CommandBuilder {
             .iter()
             .map(|path| {
                 let file = scheduler.input_file(path.clone())?;
-                self.map_exec_path(path, &file.exec_path.to_str().unwrap().into());
-                self.map_out_path(path, &file.out_path.to_str().unwrap().into());
+                self.map_exec_path(path, &file.exec_path);
+                self.map_out_path(path, &file.out_path);
                 self.inputs.push(file.id);
                 Ok(file.out_path.clone())
             })
@@ -94,8 +169,8 @@ impl CommandBuilder {
         scheduler: &mut Scheduler,
     ) -> Result<PathBuf, anyhow::Error> {
         scheduler.output_file(path).map(|file| {
-            self.map_exec_path(path, &file.exec_path.to_str().unwrap().into());
-            self.map_out_path(path, &file.out_path.to_str().unwrap().into());
+            self.map_exec_path(path, &file.exec_path);
+            self.map_out_path(path, &file.out_path);
             self.outputs.push(file.id);
             file.out_path.clone()
         })
@@ -111,14 +186,70 @@ impl CommandBuilder {
             .iter()
             .map(|path| {
                 let file = scheduler.output_file(path)?;
-                self.map_exec_path(path, &file.exec_path.to_str().unwrap().into());
-                self.map_out_path(path, &file.out_path.to_str().unwrap().into());
+                self.map_exec_path(path, &file.exec_path);
+                self.map_out_path(path, &file.out_path);
                 self.outputs.push(file.id);
                 Ok(file.out_path.clone())
             })
             .collect()
     }
 
+    /// Redirect stdin from an input file and stdout/stderr to output files of a custom command
+    pub fn redirects(
+        &mut self,
+        redirects: Redirects,
+        scheduler: &mut Scheduler,
+    ) -> Result<(), anyhow::Error> {
+        let stdin = redirects
+            .stdin
+            .map(|x| self.input(&x, scheduler))
+            .transpose()?;
+        let stdout = redirects
+            .stdout
+            .map(|x| self.output(&x, scheduler))
+            .transpose()?;
+        let stderr = redirects
+            .stderr
+            .map(|x| self.output(&x, scheduler))
+            .transpose()?;
+        let to_string = |x: PathBuf| self.path_arg(&x);
+        self.redirects = Redirects {
+            stdin: stdin.map(to_string),
+            stdout: stdout.map(to_string),
+            stderr: stderr.map(to_string),
+            stderr_to_stdout: redirects.stderr_to_stdout,
+        };
+        Ok(())
+    }
+
+    /// Kill a custom command running longer, must be called before custom_command_executor()
+    pub fn timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Decide success of a custom command not only by its exit code, must be called before custom_command_executor()
+    pub fn success_criteria(&mut self, criteria: SuccessCriteria) {
+        self.success_criteria = criteria;
+    }
+
+    /// Pass args of a custom command in a response file, must be called before custom_command_executor()
+    pub fn response_file(
+        &mut self,
+        response_file: Option<ResponseFile>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(x) = &response_file {
+            if x.first_arg > self.args_with_exec_paths.len() {
+                bail!(
+                    "first arg of response file out of range: {} > {}",
+                    x.first_arg,
+                    self.args_with_exec_paths.len()
+                );
+            }
+        }
+        self.response_file = response_file;
+        Ok(())
+    }
+
     pub fn custom_command_executor(
         &mut self,
         executable: String,
@@ -127,17 +258,61 @@ impl CommandBuilder {
     ) -> Result<(), anyhow::Error> {
         let file = scheduler.executable(executable)?;
         self.inputs.push(file.id);
-        self.executor = Some(Executor::CustomCommand(CustomCommandExecutor {
-            executable: file.exec_path.to_str().unwrap().into(),
+        let mut executable = self.path_arg(&file.exec_path);
+        if self.working_dir.is_some() && !executable.contains('/') {
+            // avoid lookup in PATH
+            executable = format!("./{executable}");
+        }
+        self.executor = Some(Executor::CustomCommand(Box::new(CustomCommandExecutor {
+            executable,
             args: self.args_with_out_paths.clone(),
             env,
-        }));
+            redirects: self.redirects.clone(),
+            working_dir: self.working_dir.clone(),
+            timeout: self.timeout,
+            success_criteria: self.success_criteria.clone(),
+            response_file: self.response_file.clone(),
+        })));
+        Ok(())
+    }
+
+    pub fn sandbox_strategy(&mut self, strategy: Option<SandboxStrategy>) {
+        self.sandbox_strategy = strategy;
+    }
+
+    pub fn resources(&mut self, resources: ResourceRequest) {
+        self.resources = resources;
+    }
+
+    /// The command reads files which are not declared as inputs, e.g. headers listed in a depfile
+    /// written by the command itself. It is run without sandbox and its result is not cached.
+    pub fn undeclared_inputs(&mut self) {
+        self.undeclared_inputs = true;
+    }
+
+    /// Read additional inputs from a depfile created by another command, e.g. headers found by `cc -M`.
+    ///
+    /// Paths within are relative to the working dir of this command.
+    pub fn deps_file(
+        &mut self,
+        path: &String,
+        scheduler: &mut Scheduler,
+    ) -> Result<(), anyhow::Error> {
+        self.input(path, scheduler)?;
+        self.deps_file = self.inputs.last().cloned();
         Ok(())
     }
 
     pub fn task_executor(&mut self, f: TaskFn) {
         self.executor = Some(Executor::Task(TaskExecutor {
-            f,
+            f: TaskFunction::Blocking(f),
+            args: self.args_with_out_paths.clone(),
+        }));
+    }
+
+    pub fn async_task_executor(&mut self, f: AsyncTaskFn) {
+        self.executor = Some(Executor::Task(TaskExecutor {
+            f: TaskFunction::Async(f),
             args: self.args_with_out_paths.clone(),
         }));
     }
@@ -149,6 +324,10 @@ impl CommandBuilder {
             inputs: self.inputs,
             outputs: self.outputs,
             executor: self.executor.unwrap(),
+            sandbox_strategy: self.sandbox_strategy,
+            resources: self.resources,
+            undeclared_inputs: self.undeclared_inputs,
+            deps_file: self.deps_file,
             unfinished_deps: vec![],
             reverse_deps: vec![],
             schedule_state: ScheduleState::New,
@@ -1,4 +1,4 @@
 pub static EXECUTABLE: &str = "razel";
 pub static OUT_DIR: &str = "razel-out";
-/// TODO SANDBOX_DIR should be outside the workspace to help IDE indexer
-pub static SANDBOX_DIR: &str = "razel-out";
+/// Name of the sandbox dir within the runtime/tmp dir, outside the workspace to help IDE indexer
+pub static SANDBOX_DIR: &str = "razel-sandbox";
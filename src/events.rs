@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::bazel_remote_exec::OutputFile;
+use crate::executors::{ExecutionResult, ExecutionStatus};
+
+/// Version of the event stream format, incremented on incompatible changes
+pub const EVENTS_VERSION: u32 = 1;
+
+/// Line of the event stream
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EventRecord {
+    pub version: u32,
+    /// seconds since UNIX epoch
+    pub timestamp: f64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Event emitted by the scheduler, versioned by EVENTS_VERSION
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    CommandAdded {
+        command: String,
+        command_line: String,
+    },
+    /// All dependencies are finished, command is waiting for resources
+    CommandReady {
+        command: String,
+    },
+    CommandStarted {
+        command: String,
+    },
+    CommandCacheHit {
+        command: String,
+    },
+    CommandFinished {
+        command: String,
+        status: ExecutionStatus,
+        exit_code: Option<i32>,
+        error: Option<String>,
+        cache_hit: bool,
+        /// seconds, None for cache hits
+        duration: Option<f64>,
+        outputs: Vec<OutputDigest>,
+    },
+    /// Command was not run because a dependency failed
+    CommandSkipped {
+        command: String,
+    },
+    Summary {
+        succeeded: usize,
+        failed: usize,
+        not_run: usize,
+        cache_hits: usize,
+        /// seconds
+        preparation_duration: f64,
+        /// seconds
+        execution_duration: f64,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OutputDigest {
+    pub path: String,
+    pub hash: String,
+    pub size: i64,
+}
+
+impl Event {
+    pub fn command_finished(
+        command: String,
+        execution_result: &ExecutionResult,
+        output_files: &[OutputFile],
+    ) -> Self {
+        Self::CommandFinished {
+            command,
+            status: execution_result.status.clone(),
+            exit_code: execution_result.exit_code,
+            error: execution_result.error.as_ref().map(|x| format!("{:#}", x)),
+            cache_hit: execution_result.cache_hit,
+            duration: execution_result.duration.map(|x| x.as_secs_f64()),
+            outputs: output_files
+                .iter()
+                .filter_map(|x| {
+                    x.digest.as_ref().map(|digest| OutputDigest {
+                        path: x.path.clone(),
+                        hash: digest.hash.clone(),
+                        size: digest.size_bytes,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Writes events as jsonl file, e.g. a regular file or a named pipe
+pub struct EventWriter {
+    writer: BufWriter<File>,
+}
+
+impl EventWriter {
+    pub fn new(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = File::create(path).with_context(|| format!("{:?}", path))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Write an event and flush it to allow following the stream while razel is running
+    pub fn write(&mut self, event: Event) -> Result<(), anyhow::Error> {
+        let record = EventRecord {
+            version: EVENTS_VERSION,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs_f64(),
+            event,
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn write_and_parse() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.child("events.jsonl");
+        let mut writer = EventWriter::new(&path).unwrap();
+        let event = Event::CommandStarted {
+            command: "a".into(),
+        };
+        writer.write(event.clone()).unwrap();
+        let line = std::fs::read_to_string(&path).unwrap();
+        assert!(line.starts_with(r#"{"version":1,"#));
+        assert!(line.contains(r#""event":"command_started","command":"a"}"#));
+        let record: EventRecord = serde_json::from_str(&line).unwrap();
+        assert_eq!(record.version, EVENTS_VERSION);
+        assert_eq!(record.event, event);
+    }
+
+    #[test]
+    fn command_finished() {
+        let execution_result = ExecutionResult {
+            status: ExecutionStatus::Failed,
+            exit_code: Some(1),
+            error: Some(anyhow::anyhow!("command failed with exit code 1")),
+            ..Default::default()
+        };
+        let json =
+            serde_json::to_value(Event::command_finished("a".into(), &execution_result, &[]))
+                .unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["exit_code"], 1);
+        assert_eq!(json["error"], "command failed with exit code 1");
+    }
+}
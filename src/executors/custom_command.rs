@@ -1,51 +1,408 @@
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+#[cfg(target_os = "linux")]
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
-use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use itertools::Itertools;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::executors::{ExecutionResult, ExecutionStatus, ResourceUsage};
 #[cfg(target_os = "linux")]
-use std::os::unix::process::ExitStatusExt;
+use crate::namespace::Namespace;
 #[cfg(target_os = "linux")]
-use anyhow::anyhow;
-
-use crate::executors::{ExecutionResult, ExecutionStatus};
+use crate::tracer;
+use crate::{FileAccess, Sandbox};
 
 #[derive(Clone)]
 pub struct CustomCommandExecutor {
     pub executable: String,
     pub args: Vec<String>,
     pub env: HashMap<String, String>,
+    pub redirects: Redirects,
+    /// relative to the sandbox dir, None to run in the sandbox dir itself
+    pub working_dir: Option<PathBuf>,
+    /// kill the command and its child processes if it runs longer, fails to start together with
+    /// detecting undeclared files
+    pub timeout: Option<Duration>,
+    pub success_criteria: SuccessCriteria,
+    pub response_file: Option<ResponseFile>,
+}
+
+/// Rules to decide if a custom command succeeded, like CTest test properties
+#[derive(Clone, Debug, Default)]
+pub struct SuccessCriteria {
+    /// exit codes to accept instead of 0
+    pub expected_exit_codes: Vec<i32>,
+    /// the command is expected to fail, like WILL_FAIL
+    pub will_fail: bool,
+    /// combined stdout/stderr must match one of these, the exit code is only checked if
+    /// expected_exit_codes is set
+    pub pass_regex: Vec<Regex>,
+    /// combined stdout/stderr must not match any of these
+    pub fail_regex: Vec<Regex>,
+}
+
+impl SuccessCriteria {
+    fn captures_output(&self) -> bool {
+        !self.pass_regex.is_empty() || !self.fail_regex.is_empty()
+    }
+
+    /// Platform properties to include the criteria into the action digest
+    pub fn properties(&self) -> Vec<(String, String)> {
+        let mut properties = vec![];
+        if !self.expected_exit_codes.is_empty() {
+            properties.push((
+                "expected_exit_codes".into(),
+                self.expected_exit_codes.iter().join(","),
+            ));
+        }
+        if self.will_fail {
+            properties.push(("will_fail".into(), "1".into()));
+        }
+        for x in &self.pass_regex {
+            properties.push(("pass_regex".into(), x.as_str().into()));
+        }
+        for x in &self.fail_regex {
+            properties.push(("fail_regex".into(), x.as_str().into()));
+        }
+        properties
+    }
+
+    /// Returns the reason of the failure, `exit_error` is set for a non-zero exit code
+    fn check(
+        &self,
+        exit_code: i32,
+        exit_error: Option<anyhow::Error>,
+        output: &str,
+    ) -> Option<anyhow::Error> {
+        let error = if let Some(x) = self.fail_regex.iter().find(|x| x.is_match(output)) {
+            Some(anyhow!("output matches fail regex {:?}", x.as_str()))
+        } else if !self.expected_exit_codes.is_empty()
+            && !self.expected_exit_codes.contains(&exit_code)
+        {
+            Some(anyhow!(
+                "exit code {exit_code} is not one of the expected exit codes {:?}",
+                self.expected_exit_codes
+            ))
+        } else if !self.pass_regex.is_empty() {
+            (!self.pass_regex.iter().any(|x| x.is_match(output))).then(|| {
+                anyhow!(
+                    "output does not match pass regex {:?}",
+                    self.pass_regex.iter().map(|x| x.as_str()).collect_vec()
+                )
+            })
+        } else if self.expected_exit_codes.is_empty() {
+            exit_error
+        } else {
+            None
+        };
+        match (self.will_fail, error) {
+            (true, Some(_)) => None,
+            (true, None) => Some(anyhow!("command succeeded but is expected to fail")),
+            (false, error) => error,
+        }
+    }
+}
+
+/// Args of a custom command to pass in a response file, e.g. to not exceed ARG_MAX
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ResponseFile {
+    /// index of the first arg to write into the response file, all following args are written too
+    pub first_arg: usize,
+    #[serde(default)]
+    pub style: ResponseFileStyle,
+}
+
+/// Quoting of args within a response file
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResponseFileStyle {
+    /// separated by spaces, special chars escaped with backslash
+    #[default]
+    Gcc,
+    /// separated by spaces, quoted like parsed by CommandLineToArgvW
+    Msvc,
+    /// one arg per line without quoting
+    OnePerLine,
+}
+
+/// File name of the response file within the working dir of the command
+static RESPONSE_FILE_NAME: &str = "razel.rsp";
+
+impl ResponseFile {
+    /// Platform property for the action digest, the args within the response file are part of it anyway
+    pub fn property(&self) -> (String, String) {
+        (
+            "response_file".into(),
+            format!("{} {:?}", self.first_arg, self.style),
+        )
+    }
+
+    pub fn content(&self, args: &[String]) -> Result<String, anyhow::Error> {
+        let args = &args[self.first_arg..];
+        Ok(match self.style {
+            ResponseFileStyle::Gcc => args.iter().map(|x| Self::quote_gcc(x)).join(" "),
+            ResponseFileStyle::Msvc => args.iter().map(|x| Self::quote_msvc(x)).join(" "),
+            ResponseFileStyle::OnePerLine => {
+                if let Some(x) = args.iter().find(|x| x.contains('\n')) {
+                    anyhow::bail!("arg with newline not supported in response file: {x:?}");
+                }
+                args.iter().map(|x| format!("{x}\n")).collect()
+            }
+        })
+    }
+
+    fn quote_gcc(arg: &str) -> String {
+        if arg.is_empty() {
+            return "''".into();
+        }
+        let mut quoted = String::with_capacity(arg.len());
+        for c in arg.chars() {
+            if c.is_whitespace() || matches!(c, '\\' | '\'' | '"') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted
+    }
+
+    fn quote_msvc(arg: &str) -> String {
+        if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '"']) {
+            return arg.into();
+        }
+        let mut quoted = String::with_capacity(arg.len() + 2);
+        quoted.push('"');
+        let mut backslashes = 0;
+        for c in arg.chars() {
+            match c {
+                '\\' => backslashes += 1,
+                '"' => {
+                    // backslashes before a quote and the quote itself need to be escaped
+                    quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                    backslashes = 0;
+                }
+                _ => {
+                    quoted.extend(std::iter::repeat_n('\\', backslashes));
+                    backslashes = 0;
+                }
+            }
+            if c != '\\' {
+                quoted.push(c);
+            }
+        }
+        // trailing backslashes would escape the closing quote
+        quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+        quoted.push('"');
+        quoted
+    }
+}
+
+/// Files to connect to the standard streams of a custom command, relative to its working directory
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Redirects {
+    pub stdin: Option<String>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    /// like `2>&1`
+    pub stderr_to_stdout: bool,
+}
+
+impl Redirects {
+    /// Shell syntax of the redirections, e.g. `["<", "in.txt", ">", "out.txt"]`
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(x) = &self.stdin {
+            args.extend(["<".into(), x.clone()]);
+        }
+        if let Some(x) = &self.stdout {
+            args.extend([">".into(), x.clone()]);
+        }
+        if let Some(x) = &self.stderr {
+            args.extend(["2>".into(), x.clone()]);
+        }
+        if self.stderr_to_stdout {
+            args.push("2>&1".into());
+        }
+        args
+    }
+
+    fn apply(&self, command: &mut std::process::Command, dir: &Path) -> Result<(), anyhow::Error> {
+        if let Some(x) = &self.stdin {
+            let path = dir.join(x);
+            command.stdin(File::open(&path).with_context(|| format!("stdin: {:?}", path))?);
+        }
+        if let Some(x) = &self.stdout {
+            let path = dir.join(x);
+            let file = File::create(&path).with_context(|| format!("stdout: {:?}", path))?;
+            if self.stderr_to_stdout {
+                command.stderr(file.try_clone()?);
+            }
+            command.stdout(file);
+        }
+        if let Some(x) = &self.stderr {
+            let path = dir.join(x);
+            command.stderr(File::create(&path).with_context(|| format!("stderr: {:?}", path))?);
+        }
+        Ok(())
+    }
+}
+
+/// Removes a file when dropped, i.e. on every return path
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
 }
 
 impl CustomCommandExecutor {
-    pub async fn exec(&self, sandbox_dir: Option<PathBuf>) -> ExecutionResult {
+    pub async fn exec(&self, sandbox: Option<&Sandbox>) -> ExecutionResult {
         let mut result: ExecutionResult = Default::default();
-        let mut child = match tokio::process::Command::new(&self.executable)
-            .env_clear()
-            .envs(&self.env)
-            .args(&self.args)
-            .current_dir(sandbox_dir.unwrap_or(".".into()))
-            .spawn()
-        {
-            Ok(child) => child,
+        let mut dir = sandbox.map_or(PathBuf::from("."), |x| x.dir.clone());
+        if let Some(working_dir) = &self.working_dir {
+            dir = dir.join(working_dir);
+            if let Err(e) = std::fs::create_dir_all(&dir) {
+                result.status = ExecutionStatus::FailedToStart;
+                result.error = Some(anyhow::Error::new(e).context(format!("{:?}", dir)));
+                return result;
+            }
+        }
+        let (args, response_file) = match self.response_file_args(sandbox, &dir) {
+            Ok(x) => x,
             Err(e) => {
                 result.status = ExecutionStatus::FailedToStart;
-                result.error = Some(e.into());
+                result.error = Some(e);
                 return result;
             }
         };
-        match child.wait().await {
-            Ok(exit_status) => {
-                if exit_status.success() {
-                    result.status = ExecutionStatus::Success;
-                } else {
+        let mut command = std::process::Command::new(&self.executable);
+        command
+            .env_clear()
+            .envs(&self.env)
+            .args(&args)
+            .current_dir(&dir);
+        if let Err(e) = self.redirects.apply(&mut command, &dir) {
+            result.status = ExecutionStatus::FailedToStart;
+            result.error = Some(e);
+            return result;
+        }
+        if let Some(sandbox) = sandbox.filter(|x| x.config.hermetic) {
+            if let Err(e) = Self::isolate(&mut command, sandbox) {
+                result.status = ExecutionStatus::FailedToStart;
+                result.error = Some(e);
+                return result;
+            }
+        }
+        let output_path = self
+            .success_criteria
+            .captures_output()
+            .then(|| Self::output_path(sandbox));
+        if let Some(path) = &output_path {
+            if let Err(e) = self.capture_output(&mut command, path) {
+                result.status = ExecutionStatus::FailedToStart;
+                result.error = Some(e);
+                return result;
+            }
+        }
+        let detect_undeclared = sandbox.filter(|x| x.config.detect_undeclared);
+        let mut timed_out = false;
+        let (exit_status, resource_usage, accesses) = if detect_undeclared.is_some() {
+            if let Some(timeout) = self.timeout {
+                result.status = ExecutionStatus::FailedToStart;
+                result.error = Some(anyhow!(
+                    "timeout ({timeout:?}) is not supported together with detecting undeclared files"
+                ));
+                return result;
+            }
+            match Self::spawn_traced(command).await {
+                Ok(x) => x,
+                Err(e) => {
+                    result.status = ExecutionStatus::FailedToStart;
+                    result.error = Some(e);
+                    return result;
+                }
+            }
+        } else {
+            if self.timeout.is_some() {
+                // to kill the child processes too on timeout
+                Self::set_process_group(&mut command);
+            }
+            let child = match command.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    result.status = ExecutionStatus::FailedToStart;
+                    result.error = Some(e.into());
+                    return result;
+                }
+            };
+            let wait_result = match self.timeout {
+                Some(timeout) => {
+                    let pid = child.id();
+                    let exited = Self::wait_exited(pid);
+                    tokio::pin!(exited);
+                    let exited_result = tokio::select! {
+                        x = &mut exited => x,
+                        _ = tokio::time::sleep(timeout) => {
+                            // the child is not yet reaped, therefore its pid is not reused
+                            Self::kill(pid);
+                            timed_out = true;
+                            exited.await
+                        }
+                    };
+                    match exited_result {
+                        Ok(()) => Self::wait(child).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                None => Self::wait(child).await,
+            };
+            match wait_result {
+                Ok((exit_status, resource_usage)) => (exit_status, resource_usage, vec![]),
+                Err(e) => {
                     result.status = ExecutionStatus::Failed;
-                    self.handle_error(exit_status, &mut result);
-
+                    result.error = Some(e);
+                    return result;
                 }
-                result.exit_code = exit_status.code();
             }
-            Err(e) => {
+        };
+        // remove before checking for undeclared outputs
+        drop(response_file);
+        result.resource_usage = resource_usage;
+        result.exit_code = exit_status.code();
+        let output = output_path
+            .map(|x| Self::read_output(&x))
+            .unwrap_or_default();
+        let exit_error = (!exit_status.success()).then(|| Self::exit_error(exit_status));
+        if timed_out {
+            result.status = ExecutionStatus::Timeout;
+            result.error = Some(anyhow!(
+                "command timed out after {:?}",
+                self.timeout.unwrap()
+            ));
+            return result;
+        } else if let Some(exit_code) = result.exit_code {
+            result.error = self.success_criteria.check(exit_code, exit_error, &output);
+        } else {
+            // killed by a signal, which is not affected by success criteria
+            result.error = exit_error;
+        }
+        result.status = if result.error.is_none() {
+            ExecutionStatus::Success
+        } else {
+            ExecutionStatus::Failed
+        };
+        if let Some(sandbox) = detect_undeclared.filter(|_| result.success()) {
+            if let Err(e) = sandbox.check_undeclared(&accesses).await {
                 result.status = ExecutionStatus::Failed;
-                result.error = Some(e.into());
+                result.error = Some(e);
             }
         }
         result
@@ -56,36 +413,232 @@ impl CustomCommandExecutor {
             .iter()
             .chain(self.args.iter())
             .cloned()
+            .chain(self.redirects.args())
             .collect()
     }
 
-    #[cfg(target_os = "windows")]
-    fn handle_error(&self, _exit_status: ExitStatus, _result: &mut ExecutionResult) {
-    } 
     #[cfg(target_os = "linux")]
-    fn handle_error(&self, exit_status: ExitStatus, result: &mut ExecutionResult) {
+    fn isolate(
+        command: &mut std::process::Command,
+        sandbox: &Sandbox,
+    ) -> Result<(), anyhow::Error> {
+        let namespace = Namespace::new(sandbox)?;
+        unsafe {
+            command.pre_exec(move || namespace.enter());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn isolate(
+        _command: &mut std::process::Command,
+        _sandbox: &Sandbox,
+    ) -> Result<(), anyhow::Error> {
+        anyhow::bail!("hermetic sandbox is only supported on Linux")
+    }
+
+    /// Wait for the command in a blocking thread to get its resource usage
+    #[cfg(target_os = "linux")]
+    async fn wait(
+        child: std::process::Child,
+    ) -> Result<(ExitStatus, Option<ResourceUsage>), anyhow::Error> {
+        Ok(tokio::task::spawn_blocking(move || {
+            let pid = child.id() as libc::pid_t;
+            let mut status = 0;
+            let mut rusage = unsafe { std::mem::zeroed() };
+            loop {
+                if unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } == pid {
+                    return Ok((
+                        ExitStatus::from_raw(status),
+                        Some(ResourceUsage::from(&rusage)),
+                    ));
+                }
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        })
+        .await??)
+    }
+
+    /// Wait until the child exited without reaping it, wait() must be called afterwards
+    #[cfg(target_os = "linux")]
+    async fn wait_exited(pid: u32) -> Result<(), anyhow::Error> {
+        Ok(tokio::task::spawn_blocking(move || loop {
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            let flags = libc::WEXITED | libc::WNOWAIT;
+            if unsafe { libc::waitid(libc::P_PID, pid as libc::id_t, &mut info, flags) } == 0 {
+                return Ok(());
+            }
+            let e = std::io::Error::last_os_error();
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        })
+        .await??)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn wait_exited(_pid: u32) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn wait(
+        mut child: std::process::Child,
+    ) -> Result<(ExitStatus, Option<ResourceUsage>), anyhow::Error> {
+        let exit_status = tokio::task::spawn_blocking(move || child.wait()).await??;
+        Ok((exit_status, None))
+    }
+
+    /// Execute the command and trace all opened files
+    #[cfg(target_os = "linux")]
+    async fn spawn_traced(
+        command: std::process::Command,
+    ) -> Result<(ExitStatus, Option<ResourceUsage>, Vec<FileAccess>), anyhow::Error> {
+        let (exit_status, rusage, accesses) =
+            tokio::task::spawn_blocking(move || tracer::trace(command)).await??;
+        Ok((exit_status, Some(ResourceUsage::from(&rusage)), accesses))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn spawn_traced(
+        _command: std::process::Command,
+    ) -> Result<(ExitStatus, Option<ResourceUsage>, Vec<FileAccess>), anyhow::Error> {
+        anyhow::bail!("detecting undeclared inputs is only supported on Linux")
+    }
+
+    /// File to capture stdout/stderr, outside of the sandbox to not be an undeclared output
+    fn output_path(sandbox: Option<&Sandbox>) -> PathBuf {
+        match sandbox {
+            Some(x) => x.dir.with_extension("output"),
+            None => Self::temp_path("output"),
+        }
+    }
+
+    /// Unique path in the tmp dir for commands executed without sandbox
+    fn temp_path(name: &str) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        std::env::temp_dir().join(format!(
+            "razel-{name}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    /// Write the response file if requested.
+    ///
+    /// Returns the args to pass and the response file, which is removed when dropped.
+    fn response_file_args(
+        &self,
+        sandbox: Option<&Sandbox>,
+        dir: &Path,
+    ) -> Result<(Vec<String>, Option<RemoveOnDrop>), anyhow::Error> {
+        let response_file = match &self.response_file {
+            Some(x) => x,
+            None => return Ok((self.args.clone(), None)),
+        };
+        let (path, arg) = match sandbox {
+            Some(_) => (dir.join(RESPONSE_FILE_NAME), RESPONSE_FILE_NAME.into()),
+            None => {
+                let path = Self::temp_path("rsp");
+                let arg = path.to_str().unwrap().to_string();
+                (path, arg)
+            }
+        };
+        std::fs::write(&path, response_file.content(&self.args)?)
+            .with_context(|| format!("Failed to write response file: {path:?}"))?;
+        let mut args = self.args[..response_file.first_arg].to_vec();
+        args.push(format!("@{arg}"));
+        Ok((args, Some(RemoveOnDrop(path))))
+    }
+
+    /// Write stdout and stderr which are not redirected into the file
+    fn capture_output(
+        &self,
+        command: &mut std::process::Command,
+        path: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let file = File::create(path).with_context(|| format!("{:?}", path))?;
+        if self.redirects.stdout.is_none() {
+            command.stdout(file.try_clone()?);
+        }
+        if self.redirects.stderr.is_none() && !self.redirects.stderr_to_stdout {
+            command.stderr(file);
+        }
+        Ok(())
+    }
+
+    /// Read and remove the captured output, it's forwarded to stdout to be visible like without capturing
+    fn read_output(path: &Path) -> String {
+        let output = std::fs::read(path).unwrap_or_default();
+        std::fs::remove_file(path).ok();
+        std::io::stdout().write_all(&output).ok();
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_process_group(command: &mut std::process::Command) {
+        command.process_group(0);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_process_group(_command: &mut std::process::Command) {}
+
+    /// Kill the process group of the child, which must not be reaped yet
+    #[cfg(target_os = "linux")]
+    fn kill(pid: u32) {
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn kill(_pid: u32) {
+        log::warn!("killing commands on timeout is only supported on Linux");
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn exit_error(exit_status: ExitStatus) -> anyhow::Error {
+        match exit_status.code() {
+            Some(exit_code) => anyhow!("command failed with exit code {exit_code}"),
+            None => anyhow!("command failed"),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn exit_error(exit_status: ExitStatus) -> anyhow::Error {
         if exit_status.core_dumped() {
-            result.error = Some(anyhow!(
+            anyhow!(
                 "command crashed with signal {}",
                 exit_status.signal().unwrap()
-            ));
+            )
         } else if let Some(signal) = exit_status.signal() {
-            result.error = Some(anyhow!("command terminated by signal {signal}"));
+            anyhow!("command terminated by signal {signal}")
         } else if let Some(signal) = exit_status.stopped_signal() {
-            result.error = Some(anyhow!("command stopped by {signal}"));
+            anyhow!("command stopped by {signal}")
         } else if let Some(exit_code) = exit_status.code() {
-            result.error = Some(anyhow!("command failed with exit code {exit_code}"));
+            anyhow!("command failed with exit code {exit_code}")
         } else {
-            result.error = Some(anyhow!("command failed"));
+            anyhow!("command failed")
         }
-    } 
-
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::executors::ExecutionStatus;
-    use crate::Scheduler;
+    use std::time::Duration;
+
+    use regex::Regex;
+    use temp_dir::TempDir;
+
+    use crate::executors::{
+        ExecutionStatus, Executor, ResponseFile, ResponseFileStyle, SuccessCriteria,
+    };
+    use crate::{CommandBuilder, Sandbox, SandboxConfig, Scheduler};
+
+    use super::RESPONSE_FILE_NAME;
 
     #[tokio::test]
     async fn exec_ok() {
@@ -151,6 +704,206 @@ mod tests {
         assert!(result.error.is_some());
     }
 
+    fn custom_command(
+        args: &[&str],
+        timeout: Option<Duration>,
+        success_criteria: SuccessCriteria,
+    ) -> Executor {
+        let mut scheduler = Scheduler::new();
+        let mut builder = CommandBuilder::new(
+            "test".into(),
+            args.iter().skip(1).map(|x| x.to_string()).collect(),
+        );
+        builder.timeout(timeout);
+        builder.success_criteria(success_criteria);
+        builder
+            .custom_command_executor(args[0].into(), Default::default(), &mut scheduler)
+            .unwrap();
+        let id = scheduler.push(builder).unwrap();
+        scheduler.get_command(id).unwrap().executor.clone()
+    }
+
+    #[tokio::test]
+    async fn exec_success_criteria() {
+        let regex = |x: &str| vec![Regex::new(x).unwrap()];
+        let will_fail = SuccessCriteria {
+            will_fail: true,
+            ..Default::default()
+        };
+        let exec = |args: &'static [&'static str], criteria: SuccessCriteria| async move {
+            custom_command(args, None, criteria).exec(None).await
+        };
+        assert!(exec(&["cmake", "-E", "false"], will_fail.clone())
+            .await
+            .success());
+        let result = exec(&["cmake", "-E", "true"], will_fail).await;
+        assert_eq!(result.status, ExecutionStatus::Failed);
+        assert_eq!(
+            result.error.unwrap().to_string(),
+            "command succeeded but is expected to fail"
+        );
+        let pass = SuccessCriteria {
+            pass_regex: regex("^hel+o"),
+            ..Default::default()
+        };
+        assert!(exec(&["cmake", "-E", "echo", "hello"], pass.clone())
+            .await
+            .success());
+        assert!(!exec(&["cmake", "-E", "echo", "world"], pass)
+            .await
+            .success());
+        let fail = SuccessCriteria {
+            fail_regex: regex("error"),
+            ..Default::default()
+        };
+        let result = exec(&["cmake", "-E", "echo", "an error"], fail.clone()).await;
+        assert_eq!(
+            result.error.unwrap().to_string(),
+            "output matches fail regex \"error\""
+        );
+        assert!(exec(&["cmake", "-E", "echo", "ok"], fail.clone())
+            .await
+            .success());
+        assert!(!exec(&["cmake", "-E", "false"], fail).await.success());
+        let exit_codes = SuccessCriteria {
+            expected_exit_codes: vec![1, 2],
+            ..Default::default()
+        };
+        assert!(exec(&["cmake", "-E", "false"], exit_codes.clone())
+            .await
+            .success());
+        let result = exec(&["cmake", "-E", "true"], exit_codes).await;
+        assert_eq!(result.status, ExecutionStatus::Failed);
+        assert_eq!(
+            result.error.unwrap().to_string(),
+            "exit code 0 is not one of the expected exit codes [1, 2]"
+        );
+    }
+
+    #[test]
+    fn response_file_content() {
+        let args = ["-c", "a b", "", "x\"y\\", "c"].map(String::from);
+        let content = |style| {
+            ResponseFile {
+                first_arg: 1,
+                style,
+            }
+            .content(&args)
+            .unwrap()
+        };
+        assert_eq!(content(ResponseFileStyle::Gcc), r#"a\ b '' x\"y\\ c"#);
+        assert_eq!(content(ResponseFileStyle::Msvc), r#""a b" "" "x\"y\\" c"#);
+        assert_eq!(content(ResponseFileStyle::OnePerLine), "a b\n\nx\"y\\\nc\n");
+    }
+
+    #[tokio::test]
+    async fn exec_response_file() {
+        let mut scheduler = Scheduler::new();
+        let script = r#"test "$(cat "${0#@}")" = 'a\ b c'"#;
+        let mut builder = CommandBuilder::new(
+            "test".into(),
+            ["-c", script, "a b", "c"].map(String::from).to_vec(),
+        );
+        builder
+            .response_file(Some(ResponseFile {
+                first_arg: 2,
+                style: ResponseFileStyle::Gcc,
+            }))
+            .unwrap();
+        builder
+            .custom_command_executor("sh".into(), Default::default(), &mut scheduler)
+            .unwrap();
+        let id = scheduler.push(builder).unwrap();
+        let executor = scheduler.get_command(id).unwrap().executor.clone();
+        let result = executor.exec(None).await;
+        assert!(result.success(), "{:?}", result.error);
+    }
+
+    #[tokio::test]
+    async fn response_file_is_removed_if_failed_to_start() {
+        let mut scheduler = Scheduler::new();
+        let mut builder = CommandBuilder::new("test".into(), vec!["a".into()]);
+        builder
+            .response_file(Some(ResponseFile {
+                first_arg: 0,
+                style: ResponseFileStyle::Gcc,
+            }))
+            .unwrap();
+        builder
+            .custom_command_executor(
+                "./hopefully-not-existing-command-to-test-razel".into(),
+                Default::default(),
+                &mut scheduler,
+            )
+            .unwrap();
+        let id = scheduler.push(builder).unwrap();
+        let executor = scheduler.get_command(id).unwrap().executor.clone();
+        let sandbox = Sandbox::new(
+            &"response_file_is_removed_if_failed_to_start".into(),
+            Default::default(),
+            vec![],
+            vec![],
+        );
+        sandbox.create().await.unwrap();
+        let result = executor.exec(Some(&sandbox)).await;
+        assert_eq!(result.status, ExecutionStatus::FailedToStart);
+        assert!(!sandbox.dir.join(RESPONSE_FILE_NAME).exists());
+        sandbox.destroy().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn exec_timeout() {
+        let executor = custom_command(
+            &["cmake", "-E", "sleep", "10"],
+            Some(Duration::from_millis(100)),
+            Default::default(),
+        );
+        let start = std::time::Instant::now();
+        let result = executor.exec(None).await;
+        assert_eq!(result.status, ExecutionStatus::Timeout);
+        assert!(result.error.is_some());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn exec_timeout_kills_child_processes() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.child("written-after-timeout");
+        let script = format!("(sleep 1 && touch {}) & wait", file.to_str().unwrap());
+        let executor = custom_command(
+            &["sh", "-c", &script],
+            Some(Duration::from_millis(100)),
+            Default::default(),
+        );
+        let result = executor.exec(None).await;
+        assert_eq!(result.status, ExecutionStatus::Timeout);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!file.exists());
+    }
+
+    #[tokio::test]
+    async fn exec_timeout_with_detect_undeclared() {
+        let executor = custom_command(
+            &["cmake", "-E", "true"],
+            Some(Duration::from_secs(10)),
+            Default::default(),
+        );
+        let config = SandboxConfig {
+            detect_undeclared: true,
+            ..Default::default()
+        };
+        let sandbox = Sandbox::new(
+            &"exec_timeout_with_detect_undeclared".into(),
+            config,
+            vec![],
+            vec![],
+        );
+        sandbox.create().await.unwrap();
+        let result = executor.exec(Some(&sandbox)).await;
+        sandbox.destroy().await.unwrap();
+        assert_eq!(result.status, ExecutionStatus::FailedToStart);
+    }
+
     /* TODO
     #[tokio::test]
     async fn exec_kill() {
@@ -1,18 +1,22 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
 
 use crate::executors::{CustomCommandExecutor, TaskExecutor};
+use crate::Sandbox;
 
 #[derive(Clone)]
 pub enum Executor {
-    CustomCommand(CustomCommandExecutor),
+    CustomCommand(Box<CustomCommandExecutor>),
     Task(TaskExecutor),
 }
 
 impl Executor {
-    pub async fn exec(&self, sandbox_dir: Option<PathBuf>) -> ExecutionResult {
+    pub async fn exec(&self, sandbox: Option<&Sandbox>) -> ExecutionResult {
         match self {
-            Executor::CustomCommand(c) => c.exec(sandbox_dir).await,
+            Executor::CustomCommand(c) => c.exec(sandbox).await,
             Executor::Task(t) => t.exec().await,
         }
     }
@@ -35,6 +39,33 @@ impl Executor {
         }
     }
 
+    /// Working dir relative to the sandbox dir
+    pub fn working_dir(&self) -> Option<&Path> {
+        match self {
+            Executor::CustomCommand(x) => x.working_dir.as_deref(),
+            Executor::Task(_) => None,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        match self {
+            Executor::CustomCommand(x) => x.timeout,
+            Executor::Task(_) => None,
+        }
+    }
+
+    /// Properties which affect the result of the command besides its args
+    pub fn platform_properties(&self) -> Vec<(String, String)> {
+        match self {
+            Executor::CustomCommand(x) => {
+                let mut properties = x.success_criteria.properties();
+                properties.extend(x.response_file.as_ref().map(|x| x.property()));
+                properties
+            }
+            Executor::Task(_) => vec![],
+        }
+    }
+
     /// Returns if a sandbox should be used.
     ///
     /// Internally implemented tasks have well defined inputs and outputs. This might not be true
@@ -53,6 +84,9 @@ pub struct ExecutionResult {
     pub exit_code: Option<i32>,
     pub error: Option<anyhow::Error>,
     pub cache_hit: bool,
+    /// wall time of the execution, None for cache hits
+    pub duration: Option<Duration>,
+    pub resource_usage: Option<ResourceUsage>,
 }
 
 impl ExecutionResult {
@@ -61,7 +95,31 @@ impl ExecutionResult {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// Resources used by a process and its waited-for children
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourceUsage {
+    pub user_time: Duration,
+    pub system_time: Duration,
+    /// peak resident set size in bytes
+    pub max_rss: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl From<&libc::rusage> for ResourceUsage {
+    fn from(x: &libc::rusage) -> Self {
+        let duration = |x: libc::timeval| {
+            Duration::from_secs(x.tv_sec as u64) + Duration::from_micros(x.tv_usec as u64)
+        };
+        Self {
+            user_time: duration(x.ru_utime),
+            system_time: duration(x.ru_stime),
+            max_rss: x.ru_maxrss as u64 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ExecutionStatus {
     NotStarted,
     FailedToStart,
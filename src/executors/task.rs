@@ -1,19 +1,42 @@
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 
 use crate::executors::{ExecutionResult, ExecutionStatus};
 
+/// Blocking task, executed on the blocking thread pool of tokio
 pub type TaskFn = Arc<dyn Fn() -> Result<(), anyhow::Error> + Send + Sync>;
 
+pub type TaskFuture = Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send>>;
+
+/// Async task, must not block the runtime
+pub type AsyncTaskFn = Arc<dyn Fn() -> TaskFuture + Send + Sync>;
+
+#[derive(Clone)]
+pub enum TaskFunction {
+    Blocking(TaskFn),
+    Async(AsyncTaskFn),
+}
+
 #[derive(Clone)]
 pub struct TaskExecutor {
-    pub f: TaskFn,
+    pub f: TaskFunction,
     pub args: Vec<String>,
 }
 
 impl TaskExecutor {
     pub async fn exec(&self) -> ExecutionResult {
         let mut result: ExecutionResult = Default::default();
-        match (self.f)() {
+        let task_result = match &self.f {
+            TaskFunction::Blocking(f) => {
+                let f = f.clone();
+                tokio::task::spawn_blocking(move || f())
+                    .await
+                    .unwrap_or_else(|e| Err(e.into()))
+            }
+            TaskFunction::Async(f) => f().await,
+        };
+        match task_result {
             Ok(()) => {
                 result.status = ExecutionStatus::Success;
                 result.exit_code = Some(0);
@@ -30,3 +53,52 @@ impl TaskExecutor {
         self.args.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    const LONG_TASK_DURATION: Duration = Duration::from_millis(1000);
+    const SHORT_TASK_DURATION: Duration = Duration::from_millis(100);
+
+    /// Test that a long blocking task does not delay other tasks, even with a single runtime thread
+    #[tokio::test(flavor = "current_thread")]
+    async fn long_task_does_not_block_runtime() {
+        let long_task = TaskExecutor {
+            f: TaskFunction::Blocking(Arc::new(|| {
+                std::thread::sleep(LONG_TASK_DURATION);
+                Ok(())
+            })),
+            args: vec![],
+        };
+        let short_task = TaskExecutor {
+            f: TaskFunction::Async(Arc::new(|| {
+                Box::pin(async {
+                    tokio::time::sleep(SHORT_TASK_DURATION).await;
+                    Ok(())
+                })
+            })),
+            args: vec![],
+        };
+        let start = Instant::now();
+        let long_handle = tokio::spawn(async move { long_task.exec().await });
+        let short_handle = tokio::spawn(async move { short_task.exec().await });
+        assert!(short_handle.await.unwrap().success());
+        assert!(start.elapsed() < LONG_TASK_DURATION / 2);
+        assert!(long_handle.await.unwrap().success());
+        assert!(start.elapsed() >= LONG_TASK_DURATION);
+    }
+
+    #[tokio::test]
+    async fn failing_task() {
+        let task = TaskExecutor {
+            f: TaskFunction::Blocking(Arc::new(|| anyhow::bail!("failed"))),
+            args: vec![],
+        };
+        let result = task.exec().await;
+        assert_eq!(result.status, ExecutionStatus::Failed);
+        assert!(result.error.is_some());
+    }
+}
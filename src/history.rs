@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::LocalCache;
+use crate::executors::ExecutionResult;
+
+/// Number of entries kept per command
+static MAX_ENTRIES_PER_COMMAND: usize = 10;
+
+/// Measured execution of a command
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub action_digest: String,
+    /// seconds since UNIX epoch
+    pub timestamp: u64,
+    /// seconds
+    pub wall_time: f64,
+    /// seconds
+    pub user_time: Option<f64>,
+    /// seconds
+    pub system_time: Option<f64>,
+    /// peak resident set size in bytes
+    pub max_rss: Option<u64>,
+    /// output file path => size in bytes
+    pub output_sizes: BTreeMap<String, u64>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        action_digest: String,
+        execution_result: &ExecutionResult,
+        output_sizes: BTreeMap<String, u64>,
+    ) -> Option<Self> {
+        let usage = execution_result.resource_usage.as_ref();
+        Some(Self {
+            action_digest,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+            wall_time: execution_result.duration?.as_secs_f64(),
+            user_time: usage.map(|x| x.user_time.as_secs_f64()),
+            system_time: usage.map(|x| x.system_time.as_secs_f64()),
+            max_rss: usage.map(|x| x.max_rss),
+            output_sizes,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct HistoryJson {
+    name: String,
+    #[serde(flatten)]
+    entry: HistoryEntry,
+}
+
+/// Execution history of commands, stored as jsonl file in the cache dir
+#[derive(Debug, Default)]
+pub struct History {
+    path: PathBuf,
+    /// command name => entries, oldest first
+    commands: HashMap<String, Vec<HistoryEntry>>,
+}
+
+impl History {
+    pub fn default_path() -> PathBuf {
+        LocalCache::dir().join("history.jsonl")
+    }
+
+    /// Read the history, invalid entries are ignored
+    pub fn load(path: &Path) -> Self {
+        let mut history = Self {
+            path: path.into(),
+            commands: Default::default(),
+        };
+        let file = match fs::File::open(path) {
+            Ok(x) => x,
+            Err(_) => return history,
+        };
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            match serde_json::from_str::<HistoryJson>(&line) {
+                Ok(x) => history.push(x.name, x.entry),
+                Err(e) => warn!("invalid entry in {:?}: {}", path, e),
+            }
+        }
+        history
+    }
+
+    pub fn push(&mut self, name: String, entry: HistoryEntry) {
+        let entries = self.commands.entry(name).or_default();
+        entries.push(entry);
+        if entries.len() > MAX_ENTRIES_PER_COMMAND {
+            entries.remove(0);
+        }
+    }
+
+    pub fn entries(&self, name: &str) -> &[HistoryEntry] {
+        self.commands.get(name).map_or(&[], |x| x.as_slice())
+    }
+
+    /// Mean wall time of the recorded executions of a command
+    pub fn estimated_duration(&self, name: &str) -> Option<Duration> {
+        let entries = self.entries(name);
+        if entries.is_empty() {
+            return None;
+        }
+        let sum: f64 = entries.iter().map(|x| x.wall_time).sum();
+        Some(Duration::from_secs_f64(sum / entries.len() as f64))
+    }
+
+    /// Write the history atomically, replacing the existing file
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let tmp_path = self
+            .path
+            .with_extension(format!("{}.tmp", std::process::id()));
+        let mut file = fs::File::create(&tmp_path).with_context(|| format!("{:?}", tmp_path))?;
+        for (name, entries) in &self.commands {
+            for entry in entries {
+                let json = HistoryJson {
+                    name: name.clone(),
+                    entry: entry.clone(),
+                };
+                writeln!(file, "{}", serde_json::to_string(&json)?)?;
+            }
+        }
+        drop(file);
+        fs::rename(&tmp_path, &self.path).with_context(|| format!("{:?}", self.path))?;
+        Ok(())
+    }
+
+    /// Print all recorded executions of a command, oldest first
+    pub fn show(&self, name: &str) {
+        let entries = self.entries(name);
+        if entries.is_empty() {
+            println!("no history for command {:?}", name);
+            return;
+        }
+        println!(
+            "{:<12} {:>10} {:>10} {:>10} {:>12} {:>12}",
+            "action", "wall [s]", "user [s]", "sys [s]", "RSS [MiB]", "output [B]"
+        );
+        let format_secs = |x: Option<f64>| x.map_or("-".into(), |x| format!("{:.3}", x));
+        for entry in entries {
+            println!(
+                "{:<12} {:>10.3} {:>10} {:>10} {:>12} {:>12}",
+                &entry.action_digest[..entry.action_digest.len().min(12)],
+                entry.wall_time,
+                format_secs(entry.user_time),
+                format_secs(entry.system_time),
+                entry
+                    .max_rss
+                    .map_or("-".into(), |x| format!("{:.1}", x as f64 / 1024.0 / 1024.0)),
+                entry.output_sizes.values().sum::<u64>(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    fn entry(wall_time: f64) -> HistoryEntry {
+        HistoryEntry {
+            action_digest: "0123456789abcdef".into(),
+            timestamp: 0,
+            wall_time,
+            user_time: Some(wall_time / 2.0),
+            system_time: None,
+            max_rss: Some(1024),
+            output_sizes: BTreeMap::from([("out.csv".into(), 42)]),
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.child("history.jsonl");
+        let mut history = History::load(&path);
+        assert!(history.entries("a").is_empty());
+        history.push("a".into(), entry(1.0));
+        history.push("a".into(), entry(3.0));
+        history.push("b".into(), entry(5.0));
+        history.save().unwrap();
+        let history = History::load(&path);
+        assert_eq!(history.entries("a"), &[entry(1.0), entry(3.0)]);
+        assert_eq!(history.entries("b"), &[entry(5.0)]);
+        assert_eq!(
+            history.estimated_duration("a"),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(history.estimated_duration("c"), None);
+    }
+
+    #[test]
+    fn limit_entries_per_command() {
+        let mut history = History::default();
+        for i in 0..MAX_ENTRIES_PER_COMMAND + 2 {
+            history.push("a".into(), entry(i as f64));
+        }
+        let entries = history.entries("a");
+        assert_eq!(entries.len(), MAX_ENTRIES_PER_COMMAND);
+        assert_eq!(entries[0], entry(2.0));
+    }
+}
@@ -1,21 +1,43 @@
 pub use cli::*;
 pub use command::*;
+pub use events::*;
 pub use file::*;
+pub use history::*;
 pub use parse_batch::*;
+pub use parse_compdb::*;
+pub use parse_ctest::*;
+pub use parse_ninja::*;
+pub use profile::*;
+pub use progress::*;
+pub use resources::*;
 pub use rules::*;
 pub use sandbox::*;
 pub use scheduler::*;
+pub use shell_words::*;
 pub use utils::*;
 
 mod cli;
 mod command;
 pub mod config;
+mod events;
 mod file;
+mod history;
+#[cfg(target_os = "linux")]
+mod namespace;
 mod parse_batch;
+mod parse_compdb;
+mod parse_ctest;
 mod parse_jsonl;
+mod parse_ninja;
+mod profile;
+mod progress;
+mod resources;
 mod rules;
 mod sandbox;
 mod scheduler;
+mod shell_words;
+#[cfg(target_os = "linux")]
+mod tracer;
 
 pub mod bazel_remote_exec {
     pub use build::bazel::remote::execution::v2::*;
@@ -59,9 +81,11 @@ pub mod bazel_remote_exec {
 pub mod cache {
     pub use cache::*;
     pub use local_cache::*;
+    pub use stat_cache::*;
 
     mod cache;
     mod local_cache;
+    mod stat_cache;
 }
 
 pub mod executors {
@@ -76,9 +100,15 @@ pub mod executors {
 
 pub mod utils {
     pub use arena::*;
+    pub use copy::*;
+    pub use depfile::*;
+    pub use path::*;
     pub use symlink::*;
 
     mod arena;
+    mod copy;
+    mod depfile;
+    mod path;
     mod symlink;
 }
 
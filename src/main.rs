@@ -49,6 +49,16 @@ mod main {
 
     /// For simplification all tests use the same binary directory and therefore need to be run in serial
     async fn test_main(args: Vec<&str>, exp_stats: SchedulerExecStats) {
+        let exp_cache_hits = exp_stats.succeeded;
+        test_main_with_cache_hits(args, exp_stats, exp_cache_hits).await
+    }
+
+    /// Like test_main(), but some commands are not cached
+    async fn test_main_with_cache_hits(
+        args: Vec<&str>,
+        exp_stats: SchedulerExecStats,
+        exp_cache_hits: usize,
+    ) {
         let _ = env_logger::builder()
             .filter_level(log::LevelFilter::Debug)
             .is_test(true)
@@ -80,7 +90,7 @@ mod main {
             .unwrap();
             let act_stats = scheduler.run().await.unwrap();
             assert_eq!(act_stats.exec, exp_stats);
-            assert_eq!(act_stats.cache_hits, exp_stats.succeeded);
+            assert_eq!(act_stats.cache_hits, exp_cache_hits);
         }
     }
 
@@ -154,7 +164,45 @@ mod main {
         test_main(
             vec![config::EXECUTABLE, "build", "test/razel.jsonl"],
             SchedulerExecStats {
-                succeeded: 8,
+                succeeded: 10,
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[serial]
+    async fn build_copy() {
+        test_main(
+            vec![
+                config::EXECUTABLE,
+                "build",
+                "--sandbox-strategy",
+                "copy",
+                "test/razel.jsonl",
+            ],
+            SchedulerExecStats {
+                succeeded: 10,
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[serial]
+    async fn batch_hardlink() {
+        test_main(
+            vec![
+                config::EXECUTABLE,
+                "batch",
+                "--sandbox-strategy",
+                "hardlink",
+                "test/batch.sh",
+            ],
+            SchedulerExecStats {
+                succeeded: 14,
                 ..Default::default()
             },
         )
@@ -167,7 +215,54 @@ mod main {
         test_main(
             vec![config::EXECUTABLE, "batch", "test/batch.sh"],
             SchedulerExecStats {
-                succeeded: 8,
+                succeeded: 14,
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[serial]
+    async fn ninja() {
+        // header scans are not cached
+        test_main_with_cache_hits(
+            vec![config::EXECUTABLE, "ninja", "test/build.ninja"],
+            SchedulerExecStats {
+                succeeded: 9,
+                ..Default::default()
+            },
+            8,
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[serial]
+    async fn import_compdb() {
+        // header scans are not cached
+        test_main_with_cache_hits(
+            vec![
+                config::EXECUTABLE,
+                "import-compdb",
+                "test/compdb/compile_commands.json",
+            ],
+            SchedulerExecStats {
+                succeeded: 4,
+                ..Default::default()
+            },
+            2,
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[serial]
+    async fn ctest() {
+        test_main(
+            vec![config::EXECUTABLE, "ctest", "test/ctest/tests.json"],
+            SchedulerExecStats {
+                succeeded: 4,
                 ..Default::default()
             },
         )
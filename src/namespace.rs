@@ -0,0 +1,225 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr::null;
+
+use anyhow::Context;
+
+use crate::Sandbox;
+
+/// Isolation of a custom command using unprivileged Linux user and mount namespaces.
+///
+/// Everything is prepared in the parent process, [Namespace::enter()] is called in the forked
+/// child process right before exec and therefore must not allocate.
+pub struct Namespace {
+    clone_flags: libc::c_int,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    /// canonicalized source and mount point relative to the sandbox dir
+    input_mounts: Vec<(CString, CString)>,
+    workspace_dir: CString,
+    sandbox_dir: CString,
+    /// sandbox_dir and all its ancestors, to recreate it if hidden by a tmpfs mount
+    sandbox_dir_ancestors: Vec<CString>,
+}
+
+impl Namespace {
+    pub fn new(sandbox: &Sandbox) -> Result<Self, anyhow::Error> {
+        let mut clone_flags = libc::CLONE_NEWUSER | libc::CLONE_NEWNS;
+        if !sandbox.config.network {
+            clone_flags |= libc::CLONE_NEWNET;
+        }
+        // keep the current user and group within the namespace
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut input_mounts = Vec::with_capacity(sandbox.inputs.len());
+        for input in sandbox.inputs.iter().filter(|x| x.is_relative()) {
+            let src = std::fs::canonicalize(input)
+                .with_context(|| format!("Error in canonicalize({:?})", input))?;
+            input_mounts.push((c_path(&src)?, c_path(input)?));
+        }
+        let workspace_dir = std::env::current_dir()?;
+        let sandbox_dir = workspace_dir.join(&sandbox.dir);
+        let sandbox_dir_ancestors = sandbox_dir
+            .ancestors()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .map(c_path)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            clone_flags,
+            uid_map: format!("{uid} {uid} 1").into_bytes(),
+            gid_map: format!("{gid} {gid} 1").into_bytes(),
+            input_mounts,
+            workspace_dir: c_path(&workspace_dir)?,
+            sandbox_dir: c_path(&sandbox_dir)?,
+            sandbox_dir_ancestors,
+        })
+    }
+
+    /// Enter new namespaces and set up the mounts. Must be called with cwd set to the sandbox dir.
+    pub fn enter(&self) -> io::Result<()> {
+        unsafe {
+            check(libc::unshare(self.clone_flags))?;
+            write_file(b"/proc/self/setgroups\0", b"deny")?;
+            write_file(b"/proc/self/uid_map\0", &self.uid_map)?;
+            write_file(b"/proc/self/gid_map\0", &self.gid_map)?;
+            // do not propagate any mounts back to the parent namespace
+            mount(None, b"/\0", None, libc::MS_REC | libc::MS_PRIVATE)?;
+            for (src, dst) in &self.input_mounts {
+                mount(
+                    Some(src.as_bytes_with_nul()),
+                    dst.as_bytes_with_nul(),
+                    None,
+                    libc::MS_BIND,
+                )?;
+                remount_read_only(dst)?;
+            }
+            let tmpfs = Some(b"tmpfs\0".as_slice());
+            mount(tmpfs, b"/tmp\0", tmpfs, libc::MS_NOSUID | libc::MS_NODEV)?;
+            // the workspace might already be hidden if it's within /tmp
+            let mut stat: libc::stat = std::mem::zeroed();
+            if libc::stat(self.workspace_dir.as_ptr(), &mut stat) == 0 {
+                let workspace_dir = self.workspace_dir.as_bytes_with_nul();
+                mount(
+                    tmpfs,
+                    workspace_dir,
+                    tmpfs,
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                )?;
+            }
+            // cwd still references the sandbox dir which might be hidden now, mount it back
+            for dir in &self.sandbox_dir_ancestors {
+                if libc::mkdir(dir.as_ptr(), 0o755) != 0
+                    && io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST)
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            let sandbox_dir = self.sandbox_dir.as_bytes_with_nul();
+            mount(
+                Some(b".\0"),
+                sandbox_dir,
+                None,
+                libc::MS_BIND | libc::MS_REC,
+            )?;
+            check(libc::chdir(self.sandbox_dir.as_ptr()))?;
+        }
+        Ok(())
+    }
+}
+
+fn c_path(path: &Path) -> Result<CString, anyhow::Error> {
+    CString::new(path.as_os_str().as_bytes()).with_context(|| format!("{:?}", path))
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+unsafe fn write_file(path: &[u8], content: &[u8]) -> io::Result<()> {
+    let fd = libc::open(path.as_ptr() as *const libc::c_char, libc::O_WRONLY);
+    check(fd)?;
+    let written = libc::write(fd, content.as_ptr() as *const libc::c_void, content.len());
+    libc::close(fd);
+    if written != content.len() as isize {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+unsafe fn mount(
+    src: Option<&[u8]>,
+    dst: &[u8],
+    fs_type: Option<&[u8]>,
+    flags: libc::c_ulong,
+) -> io::Result<()> {
+    let as_ptr = |x: Option<&[u8]>| x.map_or(null(), |x| x.as_ptr() as *const libc::c_char);
+    check(libc::mount(
+        as_ptr(src),
+        dst.as_ptr() as *const libc::c_char,
+        as_ptr(fs_type),
+        flags,
+        null(),
+    ))
+}
+
+/// Remounting a bind mount within a user namespace requires keeping the locked flags
+unsafe fn remount_read_only(dst: &CString) -> io::Result<()> {
+    let mut stat: libc::statvfs = std::mem::zeroed();
+    check(libc::statvfs(dst.as_ptr(), &mut stat))?;
+    let mut flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+    for (st, ms) in [
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ] {
+        if stat.f_flag & st != 0 {
+            flags |= ms;
+        }
+    }
+    mount(None, dst.as_bytes_with_nul(), None, flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use crate::{Scheduler, SchedulerExecStats};
+
+    async fn run_hermetic(
+        args: Vec<&str>,
+        inputs: Vec<&str>,
+        outputs: Vec<&str>,
+        network: bool,
+    ) -> SchedulerExecStats {
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        scheduler.sandbox_config.hermetic = true;
+        scheduler.sandbox_config.network = network;
+        scheduler
+            .push_custom_command(
+                "test".into(),
+                "cmake".into(),
+                args.into_iter().map(|x| x.into()).collect(),
+                Default::default(),
+                inputs.into_iter().map(|x| x.into()).collect(),
+                outputs.into_iter().map(|x| x.into()).collect(),
+            )
+            .unwrap();
+        scheduler.run().await.unwrap().exec
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn declared_input_is_readable() {
+        let args = vec!["-E", "copy", "test/data/a.csv", "namespace.csv"];
+        let stats = run_hermetic(args, vec!["test/data/a.csv"], vec!["namespace.csv"], false).await;
+        assert_eq!(stats.succeeded, 1);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn undeclared_input_is_hidden() {
+        let args = vec!["-E", "copy", "test/data/a.csv", "namespace.csv"];
+        let stats = run_hermetic(args, vec![], vec!["namespace.csv"], true).await;
+        assert_eq!(stats.failed, 1);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn input_is_read_only() {
+        let args = vec!["-E", "copy", "test/data/f.csv", "test/data/a.csv"];
+        let inputs = vec!["test/data/a.csv", "test/data/f.csv"];
+        let stats = run_hermetic(args, inputs, vec![], false).await;
+        assert_eq!(stats.failed, 1);
+    }
+}
@@ -1,37 +1,77 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::HashMap;
+use std::fs;
+use std::iter::Peekable;
 use std::path::Path;
 
-use anyhow::Context;
-use log::info;
+use anyhow::{anyhow, bail, Context};
+use log::{info, warn};
 
-use crate::{config, parse_cli, Rules, Scheduler};
+use crate::executors::{Redirects, ResponseFile, ResponseFileStyle};
+use crate::{
+    config, parse_cli, split_shell_lines, CommandBuilder, Rules, Scheduler, ShellLine, ShellToken,
+};
+
+/// Simple command of a batch file, i.e. a stage of a pipeline
+#[derive(Debug, Default, PartialEq)]
+struct BatchCommand {
+    env: HashMap<String, String>,
+    words: Vec<String>,
+    redirects: Redirects,
+}
+
+/// File with rules which is loaded automatically if it exists next to the batch file
+static RULES_FILE_NAME: &str = "razel.rules";
 
 pub fn parse_command(
     scheduler: &mut Scheduler,
     command_line: Vec<String>,
+    rules_file: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let rules = Rules::new();
-    create_command(scheduler, &rules, "command".into(), command_line.clone())
+    let rules = load_rules(None, rules_file)?;
+    let command = BatchCommand {
+        words: command_line.clone(),
+        ..Default::default()
+    };
+    create_command(scheduler, &rules, "command".into(), command)
         .with_context(|| command_line.join(" "))
 }
 
-pub fn parse_batch_file(scheduler: &mut Scheduler, file_name: String) -> Result<(), anyhow::Error> {
+/// Parse a batch file with one command per line.
+///
+/// A pipeline is split into a chain of commands linked by intermediate output files.
+/// Rules of `razel.rules` next to the batch file and of `rules_file` override the default rules.
+pub fn parse_batch_file(
+    scheduler: &mut Scheduler,
+    file_name: String,
+    rules_file: Option<String>,
+) -> Result<(), anyhow::Error> {
     scheduler.set_workspace_dir(Path::new(&file_name).parent().unwrap());
-    let rules = Rules::new();
-    let file = File::open(&file_name).with_context(|| file_name.clone())?;
-    let file_buffered = BufReader::new(file);
-    for (line_number, line) in file_buffered.lines().enumerate() {
-        if let Ok(line) = line {
-            let line_trimmed = line.trim();
-            if line_trimmed.is_empty() || line_trimmed.starts_with("#") {
-                continue;
+    let auto_rules_file = Path::new(&file_name).with_file_name(RULES_FILE_NAME);
+    let rules = load_rules(Some(&auto_rules_file), rules_file)?;
+    let text = fs::read_to_string(&file_name).with_context(|| file_name.clone())?;
+    let lines =
+        split_shell_lines(&text).map_err(|e| anyhow!("{}:{}: {}", file_name, e.line, e.message))?;
+    let file_stem = Path::new(&file_name).file_name().unwrap().to_str().unwrap();
+    for ShellLine { line, tokens } in lines {
+        let name = format!("{}:{}", &file_name, line);
+        let stages = parse_pipeline(tokens).map_err(|e| anyhow!("{name}: {e}"))?;
+        let len = stages.len();
+        let pipe_file = |i: usize| format!("{file_stem}.{line}.pipe{}", i + 1);
+        for (i, mut command) in stages.into_iter().enumerate() {
+            let name = if len == 1 {
+                name.clone()
+            } else {
+                format!("{name}:{}", i + 1)
+            };
+            if i != 0 {
+                command.redirects.stdin = Some(pipe_file(i - 1));
+            }
+            if i + 1 != len {
+                command.redirects.stdout = Some(pipe_file(i));
             }
-            let name = format!("{}:{}", &file_name, line_number + 1);
-            let command_line: Vec<String> =
-                line.split_whitespace().map(|x| x.to_string()).collect();
-            create_command(scheduler, &rules, name.clone(), command_line.clone())
-                .with_context(|| command_line.join(" "))
+            let command_line = command.words.join(" ");
+            create_command(scheduler, &rules, name.clone(), command)
+                .with_context(|| command_line)
                 .with_context(|| format!("Failed to add command: {name}"))?;
         }
     }
@@ -39,24 +79,334 @@ pub fn parse_batch_file(scheduler: &mut Scheduler, file_name: String) -> Result<
     Ok(())
 }
 
+/// Split the tokens of a line into the commands of a pipeline
+fn parse_pipeline(tokens: Vec<ShellToken>) -> Result<Vec<BatchCommand>, anyhow::Error> {
+    let mut stages = vec![BatchCommand::default()];
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let command = stages.last_mut().unwrap();
+        match token {
+            ShellToken::Word(word) => {
+                if command.words.is_empty() {
+                    if let Some((key, value)) = parse_env_assignment(&word) {
+                        command.env.insert(key, value);
+                        continue;
+                    }
+                }
+                command.words.push(word);
+            }
+            ShellToken::Operator(op) => match op.as_str() {
+                "|" => stages.push(Default::default()),
+                "<" | "0<" => command.redirects.stdin = Some(redirect_target(&op, &mut tokens)?),
+                ">" | "1>" => command.redirects.stdout = Some(redirect_target(&op, &mut tokens)?),
+                "2>" => {
+                    let and = ShellToken::Operator("&".into());
+                    if tokens.next_if_eq(&and).is_some() {
+                        if tokens.next_if_eq(&ShellToken::Word("1".into())).is_none() {
+                            bail!("unsupported redirection `2>&`, only `2>&1` is supported");
+                        }
+                        command.redirects.stderr_to_stdout = true;
+                    } else {
+                        command.redirects.stderr = Some(redirect_target(&op, &mut tokens)?);
+                    }
+                }
+                ">>" | "1>>" | "2>>" => bail!("appending redirection `{op}` is not supported"),
+                _ => bail!("unsupported shell operator `{op}`"),
+            },
+        }
+    }
+    let len = stages.len();
+    for (i, command) in stages.iter().enumerate() {
+        if command.words.is_empty() {
+            if len == 1 {
+                bail!("variable assignment without command is not supported");
+            }
+            bail!("empty command in pipeline");
+        }
+        if i != 0 && command.redirects.stdin.is_some() {
+            bail!("stdin of a piped command cannot be redirected");
+        }
+        if i + 1 != len && command.redirects.stdout.is_some() {
+            bail!("stdout of a piped command cannot be redirected");
+        }
+    }
+    Ok(stages)
+}
+
+fn redirect_target(
+    op: &str,
+    tokens: &mut Peekable<impl Iterator<Item = ShellToken>>,
+) -> Result<String, anyhow::Error> {
+    match tokens.next() {
+        Some(ShellToken::Word(x)) => Ok(x),
+        _ => bail!("missing file name after `{op}`"),
+    }
+}
+
+fn parse_env_assignment(word: &str) -> Option<(String, String)> {
+    let (key, value) = word.split_once('=')?;
+    let mut chars = key.chars();
+    let first = chars.next()?;
+    if (first == '_' || first.is_ascii_alphabetic())
+        && chars.all(|x| x == '_' || x.is_ascii_alphanumeric())
+    {
+        Some((key.into(), value.into()))
+    } else {
+        None
+    }
+}
+
+/// Default rules overridden by the rules of the files, the auto rules file is optional
+fn load_rules(
+    auto_rules_file: Option<&Path>,
+    rules_file: Option<String>,
+) -> Result<Rules, anyhow::Error> {
+    let mut rules = Rules::new();
+    if let Some(file) = auto_rules_file.filter(|x| x.is_file()) {
+        info!("Load rules from {file:?}");
+        rules.add_file(file.to_str().unwrap())?;
+    }
+    if let Some(file) = rules_file {
+        rules.add_file(&file)?;
+    }
+    Ok(rules)
+}
+
+/// Words of a command with the words of its response files inserted
+#[derive(Debug, Default)]
+struct ExpandedWords {
+    words: Vec<String>,
+    /// index of the first word read from a response file
+    first_expanded: Option<usize>,
+    /// all response files, declared as inputs to track changes and to have them in the sandbox
+    response_files: Vec<String>,
+    /// response files which are not found, e.g. because they are created by another command
+    not_found: Vec<String>,
+}
+
+/// Replace `@file` args with the words of the response files to detect and map the files within
+fn expand_response_files(
+    scheduler: &Scheduler,
+    words: &[String],
+) -> Result<ExpandedWords, anyhow::Error> {
+    let mut expanded = ExpandedWords {
+        words: Vec::with_capacity(words.len()),
+        ..Default::default()
+    };
+    for (i, word) in words.iter().enumerate() {
+        let file = match word.strip_prefix('@').filter(|x| i != 0 && !x.is_empty()) {
+            Some(x) => x.to_string(),
+            None => {
+                expanded.words.push(word.clone());
+                continue;
+            }
+        };
+        let path = scheduler.rel_path(&file)?;
+        expanded.response_files.push(file.clone());
+        if !path.is_file() {
+            warn!("response file not found, files within are not detected: {file}");
+            expanded.words.push(word.clone());
+            expanded.not_found.push(file);
+            continue;
+        }
+        expanded.first_expanded.get_or_insert(expanded.words.len());
+        let text = fs::read_to_string(&path).with_context(|| file.clone())?;
+        let lines =
+            split_shell_lines(&text).map_err(|e| anyhow!("{}:{}: {}", file, e.line, e.message))?;
+        for token in lines.into_iter().flat_map(|x| x.tokens) {
+            match token {
+                ShellToken::Word(x) => expanded.words.push(x),
+                ShellToken::Operator(x) => bail!("{file}: unsupported operator `{x}`"),
+            }
+        }
+    }
+    Ok(expanded)
+}
+
 fn create_command(
     scheduler: &mut Scheduler,
     rules: &Rules,
     name: String,
-    command_line: Vec<String>,
+    command: BatchCommand,
 ) -> Result<(), anyhow::Error> {
-    if command_line.first().unwrap() == config::EXECUTABLE {
-        parse_cli(command_line, scheduler, Some(name))?
+    let BatchCommand {
+        env,
+        words,
+        redirects,
+    } = command;
+    if words.first().unwrap() == config::EXECUTABLE {
+        if !env.is_empty() || redirects != Redirects::default() {
+            bail!("env variables and redirections are not supported for razel tasks");
+        }
+        parse_cli(words, scheduler, Some(name))?
     } else {
-        let (inputs, outputs) = if let Some(files) = rules.parse_command(&command_line)? {
-            (files.inputs, files.outputs)
-        } else {
-            (Default::default(), Default::default())
-        };
-        let mut i = command_line.into_iter();
-        let program = i.next().unwrap();
-        let args = i.collect();
-        scheduler.push_custom_command(name, program, args, Default::default(), inputs, outputs)?;
+        let builder = custom_command_builder(scheduler, rules, name, env, words, redirects)?;
+        scheduler.push(builder)?;
     }
     Ok(())
 }
+
+fn custom_command_builder(
+    scheduler: &mut Scheduler,
+    rules: &Rules,
+    name: String,
+    env: HashMap<String, String>,
+    words: Vec<String>,
+    redirects: Redirects,
+) -> Result<CommandBuilder, anyhow::Error> {
+    let ExpandedWords {
+        words,
+        first_expanded,
+        response_files,
+        not_found,
+    } = expand_response_files(scheduler, &words)?;
+    let rule_words = words
+        .iter()
+        .filter(|x| !not_found.iter().any(|y| x.strip_prefix('@') == Some(y)))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut files = rules.parse_command(&rule_words)?.unwrap_or_default();
+    files.inputs.extend(response_files);
+    if !not_found.is_empty() {
+        files.joined_prefixes.push("@".into());
+    }
+    let mut i = words.into_iter();
+    let program = i.next().unwrap();
+    let mut builder = CommandBuilder::new(name, i.collect());
+    builder.arg_prefixes(files.joined_prefixes);
+    builder.inputs(&files.inputs, scheduler)?;
+    builder.outputs(&files.outputs, scheduler)?;
+    builder.redirects(redirects, scheduler)?;
+    // razel writes the expanded args with mapped paths into a new response file
+    builder.response_file(first_expanded.map(|x| ResponseFile {
+        first_arg: x - 1,
+        style: ResponseFileStyle::Gcc,
+    }))?;
+    builder.custom_command_executor(program, env, scheduler)?;
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::*;
+
+    fn parse(text: &str) -> Result<Vec<BatchCommand>, anyhow::Error> {
+        let mut lines = split_shell_lines(text).unwrap();
+        assert_eq!(lines.len(), 1);
+        parse_pipeline(lines.remove(0).tokens)
+    }
+
+    fn words(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn env_assignments() {
+        let commands = parse("A=1 _b=x=y cmd C=2").unwrap();
+        assert_eq!(
+            commands,
+            [BatchCommand {
+                env: HashMap::from([("A".into(), "1".into()), ("_b".into(), "x=y".into())]),
+                words: words(&["cmd", "C=2"]),
+                redirects: Default::default(),
+            }]
+        );
+        assert!(parse("A=1").is_err());
+        assert_eq!(parse("1A=1").unwrap()[0].words, ["1A=1"]);
+        assert!(parse("a && b").is_err());
+    }
+
+    #[test]
+    fn redirects() {
+        let commands = parse("cmd <in.txt a >out.txt 2>err.txt").unwrap();
+        assert_eq!(commands[0].words, ["cmd", "a"]);
+        assert_eq!(
+            commands[0].redirects,
+            Redirects {
+                stdin: Some("in.txt".into()),
+                stdout: Some("out.txt".into()),
+                stderr: Some("err.txt".into()),
+                stderr_to_stdout: false,
+            }
+        );
+        assert!(
+            parse("cmd > out.txt 2>&1").unwrap()[0]
+                .redirects
+                .stderr_to_stdout
+        );
+        assert!(parse("cmd >> out.txt").is_err());
+        assert!(parse("cmd 2>&2").is_err());
+        assert!(parse("cmd >").is_err());
+    }
+
+    #[test]
+    fn pipeline() {
+        let commands = parse("a < in.txt | b x | c > out.txt").unwrap();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0].redirects.stdin, Some("in.txt".into()));
+        assert_eq!(commands[1].words, ["b", "x"]);
+        assert_eq!(commands[2].redirects.stdout, Some("out.txt".into()));
+        assert!(parse("a > out.txt | b").is_err());
+        assert!(parse("a | b < in.txt").is_err());
+        assert!(parse("a | | b").is_err());
+    }
+
+    #[test]
+    fn response_file_and_joined_args() {
+        let mut scheduler = Scheduler::new();
+        scheduler.set_workspace_dir(Path::new("test"));
+        let mut rules = Rules::new();
+        rules.add("cc -o<out> -Wl,-Map=<out> <in>...").unwrap();
+        let builder = custom_command_builder(
+            &mut scheduler,
+            &rules,
+            "cc".into(),
+            Default::default(),
+            words(&["cc", "-Wl,-Map=hello.map", "@cc.rsp"]),
+            Default::default(),
+        )
+        .unwrap();
+        let id = scheduler.push(builder).unwrap();
+        let command = scheduler.get_command(id).unwrap();
+        // hello.c, cc.rsp and the executable
+        assert_eq!(command.inputs.len(), 3);
+        assert_eq!(command.outputs.len(), 2);
+        assert_eq!(
+            command.executor.args_with_executable()[1..],
+            [
+                "-Wl,-Map=razel-out/test/hello.map",
+                "-orazel-out/test/hello.o",
+                "-c",
+                "test/hello.c"
+            ]
+        );
+    }
+
+    /// Test that the command gets a response file with mapped paths
+    #[tokio::test]
+    #[serial]
+    async fn run_response_file() {
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        scheduler.set_workspace_dir(Path::new("test"));
+        let mut rules = Rules::new();
+        rules.add("cc -o<out> <in>...").unwrap();
+        let builder = custom_command_builder(
+            &mut scheduler,
+            &rules,
+            "cc".into(),
+            Default::default(),
+            words(&["cc", "@cc.rsp"]),
+            Default::default(),
+        )
+        .unwrap();
+        scheduler.push(builder).unwrap();
+        let output = Path::new("razel-out/test/hello.o");
+        fs::remove_file(output).ok();
+        let stats = scheduler.run().await.unwrap();
+        assert_eq!(stats.exec.succeeded, 1);
+        assert!(output.is_file());
+    }
+}
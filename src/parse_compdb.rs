@@ -0,0 +1,225 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    canonicalize_path, remove_depfile_options, scan_args, split_shell_lines, CommandBuilder,
+    Scheduler, ShellToken,
+};
+
+/// Entry of a JSON Compilation Database
+#[derive(Debug, Deserialize)]
+struct CompileCommand {
+    /// working dir of the command, relative paths are relative to the compdb file
+    directory: String,
+    file: String,
+    arguments: Option<Vec<String>>,
+    command: Option<String>,
+    output: Option<String>,
+}
+
+/// Command of an entry with paths resolved
+#[derive(Debug, PartialEq)]
+struct CompileJob {
+    /// relative to cwd
+    working_dir: PathBuf,
+    args: Vec<String>,
+    file: String,
+    output: String,
+}
+
+/// Import a JSON Compilation Database, e.g. compile_commands.json created by CMake.
+///
+/// Each entry becomes a command running in the entry's directory. Header dependencies are
+/// discovered by a command running the compiler with `-M`, its depfile adds the inputs of the compile command.
+pub fn parse_compdb_file(
+    scheduler: &mut Scheduler,
+    file_name: String,
+) -> Result<(), anyhow::Error> {
+    let compdb_dir = Path::new(&file_name).parent().unwrap();
+    let text = fs::read_to_string(&file_name).with_context(|| file_name.clone())?;
+    let entries: Vec<CompileCommand> =
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse {file_name}"))?;
+    let current_dir = env::current_dir()?;
+    let jobs = entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            CompileJob::new(entry, compdb_dir, &current_dir)
+                .with_context(|| format!("{file_name}: entry {}", i + 1))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for job in jobs {
+        let name = job.working_dir.join(&job.output);
+        let name = canonicalize_path(name.to_str().unwrap());
+        job.push(scheduler, name.clone())
+            .with_context(|| format!("Failed to add command: {name}"))?;
+    }
+    info!("Added {} commands from {}", scheduler.len(), file_name);
+    Ok(())
+}
+
+impl CompileJob {
+    fn new(
+        entry: CompileCommand,
+        compdb_dir: &Path,
+        current_dir: &Path,
+    ) -> Result<Self, anyhow::Error> {
+        let directory = current_dir.join(compdb_dir).join(&entry.directory);
+        let working_dir = directory.strip_prefix(current_dir).with_context(|| {
+            format!("directory is not within cwd ({current_dir:?}): {directory:?}")
+        })?;
+        let working_dir = match canonicalize_path(working_dir.to_str().unwrap()).as_str() {
+            "." => PathBuf::new(),
+            x if x.starts_with("..") => bail!("directory is not within cwd: {directory:?}"),
+            x => PathBuf::from(x),
+        };
+        let args = match (entry.arguments, entry.command) {
+            (Some(x), _) => x,
+            (None, Some(x)) => split_command(&x)?,
+            (None, None) => bail!("entry has neither arguments nor command"),
+        };
+        if args.is_empty() {
+            bail!("empty command");
+        }
+        let (args, output) = split_output(args, entry.output)?;
+        let args = remove_depfile_options(args);
+        Ok(Self {
+            working_dir,
+            args,
+            file: entry.file,
+            output,
+        })
+    }
+
+    fn push(self, scheduler: &mut Scheduler, name: String) -> Result<(), anyhow::Error> {
+        scheduler.set_workspace_dir(&self.working_dir);
+        let deps_file = format!("{}.d", self.output);
+        // headers are not known before scanning, therefore the scan is not sandboxed and not cached
+        let mut builder =
+            CommandBuilder::new(format!("{name}.d"), scan_args(&self.args[1..], &deps_file));
+        builder.working_dir(self.working_dir.clone());
+        builder.input(&self.file, scheduler)?;
+        builder.output(&deps_file, scheduler)?;
+        builder.custom_command_executor(self.args[0].clone(), Default::default(), scheduler)?;
+        builder.undeclared_inputs();
+        scheduler.push(builder)?;
+        let mut i = self.args.into_iter();
+        let executable = i.next().unwrap();
+        let mut builder = CommandBuilder::new(name, i.collect());
+        builder.working_dir(self.working_dir);
+        builder.input(&self.file, scheduler)?;
+        builder.deps_file(&deps_file, scheduler)?;
+        builder.output(&self.output, scheduler)?;
+        builder.custom_command_executor(executable, Default::default(), scheduler)?;
+        scheduler.push(builder)?;
+        Ok(())
+    }
+}
+
+fn split_command(command: &str) -> Result<Vec<String>, anyhow::Error> {
+    let mut lines = split_shell_lines(command).map_err(|e| anyhow!(e.message))?;
+    if lines.len() != 1 {
+        bail!("command must be a single line: {command:?}");
+    }
+    lines
+        .remove(0)
+        .tokens
+        .into_iter()
+        .map(|x| match x {
+            ShellToken::Word(x) => Ok(x),
+            ShellToken::Operator(x) => bail!("unsupported shell operator `{x}` in command"),
+        })
+        .collect()
+}
+
+/// Returns the args with `-ofile` split into `-o file` to map the path, and the output file.
+///
+/// Only the last `-o` is used like by the compiler, other options like `-objcmt-migrate-all` start with `-o` too.
+fn split_output(
+    mut args: Vec<String>,
+    output: Option<String>,
+) -> Result<(Vec<String>, String), anyhow::Error> {
+    let is_output = |x: &String| match &output {
+        Some(output) => x == "-o" || x.strip_prefix("-o") == Some(output.as_str()),
+        None => x.starts_with("-o") && !x.starts_with("-obj"),
+    };
+    let Some(i) = args.iter().rposition(is_output) else {
+        let output = output.context("output file not found, neither `output` nor `-o` given")?;
+        return Ok((args, output));
+    };
+    if let Some(path) = args[i].strip_prefix("-o").filter(|x| !x.is_empty()) {
+        let path = path.to_string();
+        args[i] = "-o".into();
+        args.insert(i + 1, path);
+    }
+    let output = match output {
+        Some(x) => x,
+        None => args
+            .get(i + 1)
+            .cloned()
+            .context("path missing after `-o`")?,
+    };
+    Ok((args, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(json: &str) -> Result<CompileJob, anyhow::Error> {
+        CompileJob::new(
+            serde_json::from_str(json).unwrap(),
+            Path::new("compdb"),
+            Path::new("/ws"),
+        )
+    }
+
+    #[test]
+    fn entries() {
+        let exp = CompileJob {
+            working_dir: "compdb/build".into(),
+            args: ["cc", "-I../include", "-o", "a.o", "-c", "../a.c"]
+                .map(String::from)
+                .to_vec(),
+            file: "../a.c".into(),
+            output: "a.o".into(),
+        };
+        let command = r#"{"directory": "/ws/compdb/build", "file": "../a.c", "command": "cc '-I../include' -oa.o -c ../a.c"}"#;
+        assert_eq!(job(command).unwrap(), exp);
+        let arguments = r#"{"directory": "build", "file": "../a.c", "arguments": ["cc", "-I../include", "-o", "a.o", "-c", "../a.c"], "output": "a.o"}"#;
+        assert_eq!(job(arguments).unwrap(), exp);
+        assert!(
+            job(r#"{"directory": "/tmp", "file": "a.c", "command": "cc -o a.o a.c"}"#).is_err()
+        );
+        assert!(job(r#"{"directory": ".", "file": "a.c", "command": "cc -c a.c"}"#).is_err());
+        assert!(job(r#"{"directory": ".", "file": "a.c", "command": "cc -c a.c && x"}"#).is_err());
+    }
+
+    #[test]
+    fn depfile_options_removed() {
+        let job = job(r#"{"directory": ".", "file": "a.c", "command": "cc -MD -MT a.o -MF a.o.d -O2 -o a.o -c a.c"}"#).unwrap();
+        assert_eq!(job.working_dir, PathBuf::from("compdb"));
+        assert_eq!(job.args, ["cc", "-O2", "-o", "a.o", "-c", "a.c"]);
+        assert_eq!(
+            scan_args(&job.args[1..], "a.o.d"),
+            ["-O2", "-c", "a.c", "-M", "-MF", "a.o.d"]
+        );
+    }
+
+    #[test]
+    fn joined_output() {
+        let joined = job(r#"{"directory": ".", "file": "a.m", "command": "clang -objcmt-migrate-literals -oa.o -c a.m"}"#).unwrap();
+        assert_eq!(joined.output, "a.o");
+        assert_eq!(joined.args[1..3], ["-objcmt-migrate-literals", "-o"]);
+        let given = job(r#"{"directory": ".", "file": "a.m", "output": "a.o", "arguments": ["clang", "-oa.o", "-objcmt-migrate-all", "-c", "a.m"]}"#).unwrap();
+        assert_eq!(
+            given.args,
+            ["clang", "-o", "a.o", "-objcmt-migrate-all", "-c", "a.m"]
+        );
+    }
+}
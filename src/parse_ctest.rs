@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use log::{debug, info, warn};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::executors::SuccessCriteria;
+use crate::{canonicalize_path, CommandBuilder, CommandId, ResourceRequest, Scheduler};
+
+/// Output of `ctest --show-only=json-v1`
+#[derive(Debug, Deserialize)]
+struct CTestInfo {
+    tests: Vec<CTestTest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CTestTest {
+    name: String,
+    /// missing if the executable of the test is not available
+    #[serde(default)]
+    command: Vec<String>,
+    #[serde(default)]
+    properties: Vec<CTestProperty>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CTestProperty {
+    name: String,
+    value: Value,
+}
+
+/// Test with properties mapped onto razel features
+#[derive(Debug, Default)]
+struct CTestCommand {
+    name: String,
+    command: Vec<String>,
+    /// absolute
+    working_dir: PathBuf,
+    env: HashMap<String, String>,
+    success_criteria: SuccessCriteria,
+    timeout: Option<Duration>,
+    resources: ResourceRequest,
+    required_files: Vec<String>,
+}
+
+/// Add a command for each test of a CTest build dir.
+///
+/// The tests are listed with `ctest --show-only=json-v1`, alternatively the output of it can be given as file.
+pub fn parse_ctest(scheduler: &mut Scheduler, path: String) -> Result<(), anyhow::Error> {
+    let (build_dir, json) = if Path::new(&path).is_file() {
+        let json = fs::read_to_string(&path).with_context(|| path.clone())?;
+        (Path::new(&path).parent().unwrap().to_path_buf(), json)
+    } else {
+        (PathBuf::from(&path), show_tests(Path::new(&path))?)
+    };
+    let info: CTestInfo =
+        serde_json::from_str(&json).with_context(|| format!("Failed to parse tests of {path}"))?;
+    let build_dir = env::current_dir()?.join(build_dir);
+    let build_dir = PathBuf::from(canonicalize_path(build_dir.to_str().unwrap()));
+    for test in info.tests {
+        let name = test.name.clone();
+        if let Some(command) = CTestCommand::new(test, &build_dir)
+            .with_context(|| format!("Failed to parse test: {name}"))?
+        {
+            command
+                .push(scheduler)
+                .with_context(|| format!("Failed to add test: {name}"))?;
+        }
+    }
+    info!("Added {} commands from {}", scheduler.len(), path);
+    Ok(())
+}
+
+fn show_tests(build_dir: &Path) -> Result<String, anyhow::Error> {
+    let output = process::Command::new("ctest")
+        .arg("--show-only=json-v1")
+        .current_dir(build_dir)
+        .output()
+        .context("Failed to run ctest")?;
+    if !output.status.success() {
+        bail!(
+            "ctest failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+impl CTestCommand {
+    /// Returns None for disabled tests and tests without command
+    fn new(test: CTestTest, build_dir: &Path) -> Result<Option<Self>, anyhow::Error> {
+        if test.command.is_empty() {
+            warn!("Skip test without command: {}", test.name);
+            return Ok(None);
+        }
+        let mut command = Self {
+            name: test.name,
+            command: test.command,
+            working_dir: build_dir.into(),
+            ..Default::default()
+        };
+        for property in test.properties {
+            let value = &property.value;
+            match property.name.as_str() {
+                "DISABLED" if as_bool(value) => {
+                    info!("Skip disabled test: {}", command.name);
+                    return Ok(None);
+                }
+                "WORKING_DIRECTORY" => {
+                    let dir = build_dir.join(as_string(value)?);
+                    command.working_dir = canonicalize_path(dir.to_str().unwrap()).into();
+                }
+                "ENVIRONMENT" => {
+                    for x in as_strings(value)? {
+                        let (key, value) = x
+                            .split_once('=')
+                            .with_context(|| format!("invalid ENVIRONMENT: {x}"))?;
+                        command.env.insert(key.into(), value.into());
+                    }
+                }
+                "WILL_FAIL" => command.success_criteria.will_fail = as_bool(value),
+                "PASS_REGULAR_EXPRESSION" => {
+                    command.success_criteria.pass_regex = as_regexes(value)?
+                }
+                "FAIL_REGULAR_EXPRESSION" => {
+                    command.success_criteria.fail_regex = as_regexes(value)?
+                }
+                "TIMEOUT" => {
+                    let seconds = value.as_f64().context("TIMEOUT must be a number")?;
+                    command.timeout = (seconds > 0.0).then(|| Duration::from_secs_f64(seconds));
+                }
+                "RESOURCE_LOCK" => command.resources.locks = as_strings(value)?,
+                "RUN_SERIAL" => command.resources.exclusive = as_bool(value),
+                "PROCESSORS" => {
+                    // like ctest, which runs tests with PROCESSORS 0 on a single slot
+                    command.resources.cpus =
+                        (value.as_u64().context("PROCESSORS must be a number")? as usize).max(1)
+                }
+                "REQUIRED_FILES" => command.required_files = as_strings(value)?,
+                _ => debug!("Ignore property {} of test {}", property.name, command.name),
+            }
+        }
+        Ok(Some(command))
+    }
+
+    fn push(self, scheduler: &mut Scheduler) -> Result<CommandId, anyhow::Error> {
+        let current_dir = env::current_dir()?;
+        let working_dir = self
+            .working_dir
+            .strip_prefix(&current_dir)
+            .with_context(|| format!("working dir is not within cwd: {:?}", self.working_dir))?;
+        // REQUIRED_FILES are relative to the working dir of the test
+        let required_files = self
+            .required_files
+            .iter()
+            .map(|x| canonicalize_path(self.working_dir.join(x).to_str().unwrap()))
+            .collect();
+        let mut i = self.command.into_iter();
+        let executable = i.next().unwrap();
+        let mut builder = CommandBuilder::new(self.name, i.collect());
+        builder.working_dir(working_dir.into());
+        builder.inputs(&required_files, scheduler)?;
+        builder.timeout(self.timeout);
+        builder.success_criteria(self.success_criteria);
+        builder.custom_command_executor(executable, self.env, scheduler)?;
+        builder.resources(self.resources);
+        scheduler.push(builder)
+    }
+}
+
+fn as_bool(value: &Value) -> bool {
+    match value {
+        Value::Bool(x) => *x,
+        Value::String(x) => !matches!(
+            x.to_uppercase().as_str(),
+            "" | "0" | "OFF" | "NO" | "FALSE" | "N" | "IGNORE" | "NOTFOUND"
+        ),
+        Value::Number(x) => x.as_f64() != Some(0.0),
+        _ => false,
+    }
+}
+
+fn as_string(value: &Value) -> Result<String, anyhow::Error> {
+    value
+        .as_str()
+        .map(String::from)
+        .with_context(|| format!("expected string: {value}"))
+}
+
+/// CTest lists are arrays, single values might be strings
+fn as_strings(value: &Value) -> Result<Vec<String>, anyhow::Error> {
+    match value {
+        Value::Array(x) => x.iter().map(as_string).collect(),
+        x => Ok(vec![as_string(x)?]),
+    }
+}
+
+fn as_regexes(value: &Value) -> Result<Vec<Regex>, anyhow::Error> {
+    as_strings(value)?
+        .iter()
+        .map(|x| Regex::new(x).with_context(|| format!("invalid regex: {x}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Option<CTestCommand> {
+        CTestCommand::new(serde_json::from_str(json).unwrap(), Path::new("/build")).unwrap()
+    }
+
+    #[test]
+    fn properties() {
+        let command = parse(
+            r#"{"name": "t", "command": ["/build/t", "-v"], "properties": [
+                {"name": "WORKING_DIRECTORY", "value": "/build/sub"},
+                {"name": "ENVIRONMENT", "value": ["A=1", "B=x=y"]},
+                {"name": "WILL_FAIL", "value": true},
+                {"name": "PASS_REGULAR_EXPRESSION", "value": ["ok", "pass.*"]},
+                {"name": "FAIL_REGULAR_EXPRESSION", "value": ["error"]},
+                {"name": "TIMEOUT", "value": 1.5},
+                {"name": "RESOURCE_LOCK", "value": ["db"]},
+                {"name": "PROCESSORS", "value": 2},
+                {"name": "REQUIRED_FILES", "value": ["data.txt"]},
+                {"name": "LABELS", "value": ["unit"]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(command.command, ["/build/t", "-v"]);
+        assert_eq!(command.working_dir, PathBuf::from("/build/sub"));
+        assert_eq!(command.env["B"], "x=y");
+        assert!(command.success_criteria.will_fail);
+        assert_eq!(command.success_criteria.pass_regex.len(), 2);
+        assert_eq!(command.success_criteria.fail_regex[0].as_str(), "error");
+        assert_eq!(command.timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(command.resources.locks, ["db"]);
+        assert_eq!(command.resources.cpus, 2);
+        assert_eq!(command.required_files, ["data.txt"]);
+    }
+
+    #[test]
+    fn required_files() {
+        let mut scheduler = Scheduler::new();
+        let mut command = parse(
+            r#"{"name": "t", "command": ["/bin/true"], "properties": [
+                {"name": "REQUIRED_FILES", "value": ["data.txt", "../shared.txt"]}
+            ]}"#,
+        )
+        .unwrap();
+        command.working_dir = env::current_dir().unwrap().join("test/data");
+        let id = command.push(&mut scheduler).unwrap();
+        let inputs = scheduler.get_command(id).unwrap().inputs.clone();
+        for x in ["test/data/data.txt", "test/shared.txt"] {
+            let file = scheduler.input_file(x.into()).unwrap();
+            assert!(inputs.contains(&file.id), "{x}");
+        }
+        // the workspace dir is not changed
+        assert_eq!(
+            scheduler.rel_path(&"data.txt".into()).unwrap(),
+            PathBuf::from("data.txt")
+        );
+    }
+
+    #[test]
+    fn skipped_tests() {
+        assert!(parse(r#"{"name": "t"}"#).is_none());
+        assert!(parse(
+            r#"{"name": "t", "command": ["t"], "properties": [{"name": "DISABLED", "value": true}]}"#
+        )
+        .is_none());
+        let command = parse(r#"{"name": "t", "command": ["t"]}"#).unwrap();
+        assert_eq!(command.working_dir, PathBuf::from("/build"));
+        assert_eq!(command.timeout, None);
+    }
+}
@@ -5,13 +5,56 @@ use std::path::Path;
 
 use anyhow::Context;
 use log::info;
+use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
-use crate::{config, parse_cli, Scheduler};
+use crate::executors::{ResponseFile, SuccessCriteria};
+use crate::{config, parse_cli, CommandBuilder, ResourceRequest, SandboxStrategy, Scheduler};
 
 pub fn parse_jsonl_file(scheduler: &mut Scheduler, file_name: String) -> Result<(), anyhow::Error> {
     scheduler.set_workspace_dir(Path::new(&file_name).parent().unwrap());
-    let file = File::open(&file_name).with_context(|| file_name.clone())?;
+    for_each_json_line(&file_name, |json: RazelJson| {
+        match json {
+            RazelJson::CustomCommand(c) => {
+                let mut builder = CommandBuilder::new(c.name, c.args);
+                builder.inputs(&c.inputs, scheduler)?;
+                builder.outputs(&c.outputs, scheduler)?;
+                builder.success_criteria(c.success_criteria.try_into()?);
+                builder.response_file(c.response_file)?;
+                builder.custom_command_executor(c.executable, c.env, scheduler)?;
+                builder.sandbox_strategy(c.sandbox);
+                builder.resources(c.resources);
+                scheduler.push(builder)?;
+            }
+            RazelJson::Task(t) => {
+                let mut args: Vec<String> =
+                    vec![config::EXECUTABLE.into(), "task".into(), t.task.into()];
+                args.extend(&mut t.args.iter().map(|x| x.into()));
+                parse_cli(args.clone(), scheduler, Some(t.name.clone()))
+                    .with_context(|| format!("{}\n{}", t.name, args.join(" ")))?
+            }
+            RazelJson::Pool(p) => scheduler.add_pool(p.pool, p.depth, p.executables)?,
+            RazelJson::Lane(l) => scheduler.set_lane(l.lane, l.limit)?,
+        }
+        Ok(())
+    })?;
+    info!("Added {} commands from {}", scheduler.len(), file_name);
+    Ok(())
+}
+
+/// Read pool declarations from a jsonl file, same format as in razel.jsonl
+pub fn parse_pools_file(scheduler: &mut Scheduler, file_name: String) -> Result<(), anyhow::Error> {
+    for_each_json_line(&file_name, |p: RazelPoolJson| {
+        scheduler.add_pool(p.pool, p.depth, p.executables)
+    })
+}
+
+fn for_each_json_line<T: DeserializeOwned>(
+    file_name: &String,
+    mut f: impl FnMut(T) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let file = File::open(file_name).with_context(|| file_name.clone())?;
     let file_buffered = BufReader::new(file);
     for (line_number, line_result) in file_buffered.lines().enumerate() {
         let line = line_result?;
@@ -19,7 +62,7 @@ pub fn parse_jsonl_file(scheduler: &mut Scheduler, file_name: String) -> Result<
         if line_trimmed.is_empty() || line_trimmed.starts_with("//") {
             continue;
         }
-        let json: RazelJson = serde_json::from_str(line_trimmed).with_context(|| {
+        let json: T = serde_json::from_str(line_trimmed).with_context(|| {
             format!(
                 "failed to parse {}:{}\n{}",
                 file_name,
@@ -27,35 +70,18 @@ pub fn parse_jsonl_file(scheduler: &mut Scheduler, file_name: String) -> Result<
                 line_trimmed
             )
         })?;
-        match json {
-            RazelJson::CustomCommand(c) => {
-                scheduler.push_custom_command(
-                    c.name,
-                    c.executable,
-                    c.args,
-                    c.env,
-                    c.inputs,
-                    c.outputs,
-                )?;
-            }
-            RazelJson::Task(t) => {
-                let mut args: Vec<String> =
-                    vec![config::EXECUTABLE.into(), "task".into(), t.task.into()];
-                args.extend(&mut t.args.iter().map(|x| x.into()));
-                parse_cli(args.clone(), scheduler, Some(t.name.clone()))
-                    .with_context(|| format!("{}\n{}", t.name, args.join(" ")))?
-            }
-        }
+        f(json)?;
     }
-    info!("Added {} commands from {}", scheduler.len(), file_name);
     Ok(())
 }
 
 #[derive(Deserialize)]
 #[serde(untagged)]
 enum RazelJson {
-    CustomCommand(RazelCustomCommandJson),
+    CustomCommand(Box<RazelCustomCommandJson>),
     Task(RazelTaskJson),
+    Pool(RazelPoolJson),
+    Lane(RazelLaneJson),
 }
 
 #[derive(Deserialize)]
@@ -69,6 +95,47 @@ struct RazelCustomCommandJson {
     inputs: Vec<String>,
     #[serde(default)]
     outputs: Vec<String>,
+    #[serde(default)]
+    sandbox: Option<SandboxStrategy>,
+    #[serde(flatten)]
+    resources: ResourceRequest,
+    #[serde(flatten)]
+    success_criteria: RazelSuccessCriteriaJson,
+    /// pass args in a response file: {"first_arg": 2, "style": "gcc|msvc|one-per-line"}
+    #[serde(default)]
+    response_file: Option<ResponseFile>,
+}
+
+/// Rules to decide if a custom command succeeded, by default the exit code must be 0
+#[derive(Deserialize)]
+struct RazelSuccessCriteriaJson {
+    #[serde(default)]
+    expected_exit_codes: Vec<i32>,
+    #[serde(default)]
+    will_fail: bool,
+    /// regexes for the combined stdout/stderr
+    #[serde(default)]
+    pass_regex: Vec<String>,
+    #[serde(default)]
+    fail_regex: Vec<String>,
+}
+
+impl TryFrom<RazelSuccessCriteriaJson> for SuccessCriteria {
+    type Error = anyhow::Error;
+
+    fn try_from(json: RazelSuccessCriteriaJson) -> Result<Self, Self::Error> {
+        let regexes = |x: Vec<String>| -> Result<Vec<Regex>, anyhow::Error> {
+            x.iter()
+                .map(|x| Regex::new(x).with_context(|| format!("invalid regex: {x}")))
+                .collect()
+        };
+        Ok(SuccessCriteria {
+            expected_exit_codes: json.expected_exit_codes,
+            will_fail: json.will_fail,
+            pass_regex: regexes(json.pass_regex)?,
+            fail_regex: regexes(json.fail_regex)?,
+        })
+    }
 }
 
 #[derive(Deserialize)]
@@ -77,3 +144,20 @@ struct RazelTaskJson {
     task: String,
     args: Vec<String>,
 }
+
+/// Declares a concurrency pool
+#[derive(Deserialize)]
+struct RazelPoolJson {
+    pool: String,
+    depth: usize,
+    /// commands using these executables join the pool automatically
+    #[serde(default)]
+    executables: Vec<String>,
+}
+
+/// Declares a scheduling lane or changes its limit
+#[derive(Deserialize)]
+struct RazelLaneJson {
+    lane: String,
+    limit: usize,
+}
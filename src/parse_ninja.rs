@@ -0,0 +1,855 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context};
+use itertools::Itertools;
+use log::info;
+
+use crate::{
+    canonicalize_path, config, parse_cli, relative_path, remove_depfile_options, scan_args,
+    split_shell_lines, tasks, CommandBuilder, ResourceRequest, Scheduler, ShellToken,
+};
+
+/// Pool which is implicitly declared by ninja
+static CONSOLE_POOL: &str = "console";
+
+/// Max depth of rule variables referencing other variables, to detect cycles
+static MAX_EVALUATION_DEPTH: usize = 32;
+
+/// Parse a ninja build file and add a command for each build edge.
+///
+/// Commands are run in the dir of the build file like by ninja.
+/// Headers of edges with depfiles are found by a command running the compiler with `-M`, like for compdb.
+/// Edges with depfiles which are run by `sh` cannot be scanned, they are run without sandbox and not cached.
+pub fn parse_ninja_file(scheduler: &mut Scheduler, file_name: String) -> Result<(), anyhow::Error> {
+    let path = Path::new(&file_name);
+    let build_dir = path.parent().unwrap();
+    scheduler.set_workspace_dir(build_dir);
+    let manifest = NinjaManifest::load(build_dir, path.file_name().unwrap().to_str().unwrap())?;
+    manifest.push_commands(scheduler)?;
+    info!("Added {} commands from {}", scheduler.len(), file_name);
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct NinjaManifest {
+    build_dir: PathBuf,
+    edges: Vec<NinjaEdge>,
+    /// name and depth of declared pools
+    pools: Vec<(String, usize)>,
+}
+
+/// Build statement with evaluated paths and variables
+#[derive(Debug, Default, PartialEq)]
+struct NinjaEdge {
+    /// file:line of the build statement
+    location: String,
+    rule: String,
+    outputs: Vec<String>,
+    implicit_outputs: Vec<String>,
+    inputs: Vec<String>,
+    implicit_inputs: Vec<String>,
+    order_only_inputs: Vec<String>,
+    command: String,
+    depfile: Option<String>,
+    rspfile: Option<String>,
+    rspfile_content: String,
+    pool: Option<String>,
+    /// edge regenerates the build file, e.g. by re-running cmake
+    generator: bool,
+}
+
+impl NinjaEdge {
+    fn all_inputs(&self) -> impl Iterator<Item = &String> {
+        self.inputs
+            .iter()
+            .chain(&self.implicit_inputs)
+            .chain(&self.order_only_inputs)
+    }
+
+    fn all_outputs(&self) -> impl Iterator<Item = &String> {
+        self.outputs.iter().chain(&self.implicit_outputs)
+    }
+}
+
+/// Variables and rules of a file, subninja files get a copy of the parent scope
+#[derive(Clone, Debug, Default)]
+struct Scope {
+    vars: HashMap<String, String>,
+    /// rule name => unevaluated bindings
+    rules: HashMap<String, HashMap<String, String>>,
+}
+
+impl Scope {
+    fn lookup(&self, name: &str) -> Result<String, anyhow::Error> {
+        Ok(self.vars.get(name).cloned().unwrap_or_default())
+    }
+}
+
+/// Line with `$\n` continuations joined
+#[derive(Debug)]
+struct Line {
+    number: usize,
+    indented: bool,
+    text: String,
+}
+
+impl NinjaManifest {
+    fn load(build_dir: &Path, file_name: &str) -> Result<Self, anyhow::Error> {
+        let mut manifest = Self {
+            build_dir: build_dir.into(),
+            ..Default::default()
+        };
+        let mut scope = Scope::default();
+        scope.rules.insert("phony".into(), Default::default());
+        manifest.parse_file(file_name, &mut scope)?;
+        Ok(manifest)
+    }
+
+    /// Parse a file, the path is relative to the build dir also for included files
+    fn parse_file(&mut self, file_name: &str, scope: &mut Scope) -> Result<(), anyhow::Error> {
+        let path = self.build_dir.join(file_name);
+        let text = fs::read_to_string(&path).with_context(|| format!("{:?}", path))?;
+        let lines = logical_lines(&text);
+        let mut i = 0;
+        while i < lines.len() {
+            let line = &lines[i];
+            let location = format!("{}:{}", path.display(), line.number);
+            if line.indented {
+                bail!("{location}: unexpected indent");
+            }
+            i += 1;
+            let mut bindings = vec![];
+            while i < lines.len() && lines[i].indented {
+                bindings.push(
+                    parse_binding(&lines[i].text)
+                        .map_err(|e| anyhow!("{}:{}: {}", path.display(), lines[i].number, e))?,
+                );
+                i += 1;
+            }
+            self.parse_statement(&line.text, bindings, scope, &location)
+                .map_err(|e| anyhow!("{location}: {e:#}"))?;
+        }
+        Ok(())
+    }
+
+    fn parse_statement(
+        &mut self,
+        text: &str,
+        bindings: Vec<(String, String)>,
+        scope: &mut Scope,
+        location: &str,
+    ) -> Result<(), anyhow::Error> {
+        let (keyword, rest) = text.split_once(' ').unwrap_or((text, ""));
+        let rest = rest.trim();
+        match keyword {
+            "rule" => {
+                if !bindings.iter().any(|(key, _)| key == "command") {
+                    bail!("rule {rest} has no command");
+                }
+                scope
+                    .rules
+                    .insert(rest.into(), bindings.into_iter().collect());
+            }
+            "build" => self.parse_build(rest, bindings, scope, location)?,
+            "pool" => {
+                let depth = bindings
+                    .iter()
+                    .find(|(key, _)| key == "depth")
+                    .context("pool has no depth")?;
+                let depth = evaluate(&depth.1, &|x| scope.lookup(x))?;
+                let depth = depth
+                    .parse()
+                    .with_context(|| format!("invalid pool depth: {depth}"))?;
+                self.pools.push((rest.into(), depth));
+            }
+            "default" => {}
+            "include" | "subninja" => {
+                if !bindings.is_empty() {
+                    bail!("unexpected indent");
+                }
+                let file_name = evaluate(rest, &|x| scope.lookup(x))?;
+                if keyword == "include" {
+                    self.parse_file(&file_name, scope)?;
+                } else {
+                    self.parse_file(&file_name, &mut scope.clone())?;
+                }
+            }
+            _ => {
+                if !bindings.is_empty() {
+                    bail!("unexpected indent");
+                }
+                let (key, value) = parse_binding(text)?;
+                let value = evaluate(&value, &|x| scope.lookup(x))?;
+                scope.vars.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_build(
+        &mut self,
+        text: &str,
+        bindings: Vec<(String, String)>,
+        scope: &Scope,
+        location: &str,
+    ) -> Result<(), anyhow::Error> {
+        // edge bindings are evaluated in the file scope, paths in the edge scope
+        let bindings: HashMap<String, String> = bindings
+            .into_iter()
+            .map(|(key, value)| Ok((key, evaluate(&value, &|x| scope.lookup(x))?)))
+            .collect::<Result<_, anyhow::Error>>()?;
+        let lookup = |x: &str| match bindings.get(x) {
+            Some(x) => Ok(x.clone()),
+            None => scope.lookup(x),
+        };
+        let mut edge = NinjaEdge {
+            location: location.into(),
+            ..Default::default()
+        };
+        // 0: outputs, 1: implicit outputs, 2: rule, 3: inputs, 4: implicit inputs, 5: order-only inputs, 6: validations
+        let mut section = 0;
+        for token in split_build_line(text)? {
+            match (token, section) {
+                (BuildToken::Pipe, 0) => section = 1,
+                (BuildToken::Colon, 0 | 1) => section = 2,
+                (BuildToken::Pipe, 3) => section = 4,
+                (BuildToken::Pipe2, 3 | 4) => section = 5,
+                (BuildToken::PipeAt, 3..=5) => section = 6,
+                (BuildToken::Path(x), 2) => {
+                    edge.rule = x;
+                    section = 3;
+                }
+                (BuildToken::Path(x), _) => {
+                    let path = canonicalize_path(&evaluate(&x, &lookup)?);
+                    match section {
+                        0 => edge.outputs.push(path),
+                        1 => edge.implicit_outputs.push(path),
+                        3 => edge.inputs.push(path),
+                        4 => edge.implicit_inputs.push(path),
+                        5 => edge.order_only_inputs.push(path),
+                        _ => {}
+                    }
+                }
+                (token, _) => bail!("unexpected {token:?} in build statement"),
+            }
+        }
+        if edge.rule.is_empty() {
+            bail!("build statement without rule");
+        }
+        if edge.outputs.is_empty() && edge.implicit_outputs.is_empty() {
+            bail!("build statement without outputs");
+        }
+        let rule = scope
+            .rules
+            .get(&edge.rule)
+            .with_context(|| format!("unknown rule: {}", edge.rule))?;
+        let env = EdgeEnv {
+            scope,
+            rule,
+            bindings: &bindings,
+            inputs: &edge.inputs,
+            outputs: &edge.outputs,
+        };
+        let command = env.lookup("command", 0)?;
+        let depfile = env.lookup("depfile", 0)?;
+        let rspfile = env.lookup("rspfile", 0)?;
+        let rspfile_content = env.lookup("rspfile_content", 0)?;
+        let pool = env.lookup("pool", 0)?;
+        let generator = !env.lookup("generator", 0)?.is_empty();
+        edge.command = command;
+        edge.depfile = (!depfile.is_empty()).then(|| canonicalize_path(&depfile));
+        edge.rspfile = (!rspfile.is_empty()).then(|| canonicalize_path(&rspfile));
+        edge.rspfile_content = rspfile_content;
+        edge.pool = (!pool.is_empty()).then_some(pool);
+        edge.generator = generator;
+        self.edges.push(edge);
+        Ok(())
+    }
+
+    fn push_commands(&self, scheduler: &mut Scheduler) -> Result<(), anyhow::Error> {
+        for (name, depth) in &self.pools {
+            scheduler.add_pool(name.clone(), *depth, vec![])?;
+        }
+        let mut console_pool = false;
+        let deps = self.expanded_inputs();
+        for i in self.topological_order(&deps)? {
+            let edge = &self.edges[i];
+            if edge.pool.as_deref() == Some(CONSOLE_POOL) && !console_pool {
+                scheduler.add_pool(CONSOLE_POOL.into(), 1, vec![])?;
+                console_pool = true;
+            }
+            self.push_edge(scheduler, edge, &deps[i])
+                .with_context(|| edge.location.clone())?;
+        }
+        Ok(())
+    }
+
+    fn push_edge(
+        &self,
+        scheduler: &mut Scheduler,
+        edge: &NinjaEdge,
+        inputs: &[String],
+    ) -> Result<(), anyhow::Error> {
+        let name = edge.all_outputs().next().unwrap().clone();
+        let mut inputs = inputs.to_vec();
+        let mut outputs = edge.all_outputs().cloned().collect_vec();
+        if let Some(depfile) = &edge.depfile {
+            if !outputs.contains(depfile) {
+                outputs.push(depfile.clone());
+            }
+        }
+        // commands are run in the build dir like by ninja
+        let working_dir = scheduler.rel_path(&".".into())?;
+        // paths in the command line => paths relative to the build dir within the sandbox
+        let mut path_map: HashMap<String, String> = Default::default();
+        for input in &inputs {
+            let path = scheduler.input_file(input.clone())?.out_path.clone();
+            let path = relative_path(&path, &working_dir);
+            path_map.insert(input.clone(), path.to_str().unwrap().into());
+        }
+        for output in &outputs {
+            let path = relative_path(&scheduler.out_path(output)?, &working_dir);
+            path_map.insert(output.clone(), path.to_str().unwrap().into());
+        }
+        if let Some(rspfile) = &edge.rspfile {
+            let path = push_rspfile(scheduler, &name, rspfile, &edge.rspfile_content, &path_map)?;
+            let path = relative_path(&path, &working_dir);
+            path_map.insert(rspfile.clone(), path.to_str().unwrap().into());
+            inputs.push(rspfile.clone());
+        }
+        let map = |x: &str| map_word(x, &path_map);
+        let tokens = split_shell_lines(&edge.command)
+            .ok()
+            .filter(|x| x.len() == 1)
+            .map(|mut lines| {
+                // CMake uses `: && cmd && :` for empty pre/post link steps
+                let and = ShellToken::Operator("&&".into());
+                let noop = [ShellToken::Word(":".into())];
+                lines
+                    .remove(0)
+                    .tokens
+                    .split(|x| *x == and)
+                    .filter(|x| *x != noop)
+                    .collect_vec()
+                    .join(&and)
+            });
+        let words = tokens.as_ref().and_then(|tokens| {
+            tokens
+                .iter()
+                .map(|x| match x {
+                    ShellToken::Word(x) => Some(x.clone()),
+                    ShellToken::Operator(_) => None,
+                })
+                .collect::<Option<Vec<_>>>()
+        });
+        let is_plain_command = words.as_ref().is_some_and(|x| !x.is_empty());
+        let (executable, mut args, arg_prefixes) = match (words, tokens) {
+            (Some(words), _) if words.first().map(|x| x.as_str()) == Some(config::EXECUTABLE) => {
+                return parse_cli(words, scheduler, Some(name));
+            }
+            (Some(words), _) if !words.is_empty() => {
+                // paths are mapped by CommandBuilder
+                let mut i = words.into_iter();
+                let executable = i.next().unwrap();
+                let args = i.collect_vec();
+                let arg_prefixes = args
+                    .iter()
+                    .filter_map(|x| joined_path(x, &path_map))
+                    .map(|(prefix, _)| prefix.to_string())
+                    .unique()
+                    .collect();
+                (executable, args, arg_prefixes)
+            }
+            (Some(_), _) => bail!("empty command"),
+            (None, Some(tokens)) => {
+                // run commands with shell operators by sh, paths are mapped per word
+                let script = tokens
+                    .iter()
+                    .map(|x| match x {
+                        ShellToken::Word(x) => shell_escape(&map(x)),
+                        ShellToken::Operator(x) => x.clone(),
+                    })
+                    .join(" ");
+                ("sh".into(), vec!["-c".into(), script], vec![])
+            }
+            (None, None) => {
+                // e.g. `$VAR` or `$(...)` is not supported by the tokenizer, map space separated words
+                let script = edge.command.split(' ').map(map).join(" ");
+                ("sh".into(), vec!["-c".into(), script], vec![])
+            }
+        };
+        let inputs = inputs.into_iter().unique().collect_vec();
+        // the depfile of a compiler is created by a scan command, whose headers become inputs
+        let scanned_depfile = edge.depfile.as_ref().filter(|_| is_plain_command);
+        if let Some(depfile) = scanned_depfile {
+            args = remove_depfile_options(args);
+            outputs.retain(|x| x != depfile);
+            // headers are not known before scanning, therefore the scan is not sandboxed and not cached
+            let mut builder = CommandBuilder::new(format!("{name}.d"), scan_args(&args, depfile));
+            builder.working_dir(working_dir.clone());
+            builder.arg_prefixes(arg_prefixes.clone());
+            builder.inputs(&inputs, scheduler)?;
+            builder.output(depfile, scheduler)?;
+            builder.custom_command_executor(executable.clone(), Default::default(), scheduler)?;
+            builder.undeclared_inputs();
+            scheduler.push(builder)?;
+        }
+        let mut builder = CommandBuilder::new(name, args);
+        builder.working_dir(working_dir);
+        builder.arg_prefixes(arg_prefixes);
+        builder.inputs(&inputs, scheduler)?;
+        builder.outputs(&outputs, scheduler)?;
+        builder.custom_command_executor(executable, Default::default(), scheduler)?;
+        if let Some(pool) = &edge.pool {
+            builder.resources(ResourceRequest {
+                pool: Some(pool.clone()),
+                ..Default::default()
+            });
+        }
+        if let Some(depfile) = scanned_depfile {
+            builder.deps_file(depfile, scheduler)?;
+        } else if edge.depfile.is_some() {
+            // commands run by sh cannot be scanned, headers are only known after running them
+            builder.undeclared_inputs();
+        }
+        scheduler.push(builder)?;
+        Ok(())
+    }
+
+    /// Inputs of each edge with phony targets replaced by their inputs
+    fn expanded_inputs(&self) -> Vec<Vec<String>> {
+        let phony: HashMap<&String, &NinjaEdge> = self
+            .edges
+            .iter()
+            .filter(|x| x.rule == "phony")
+            .flat_map(|edge| edge.all_outputs().map(move |x| (x, edge)))
+            .collect();
+        self.edges
+            .iter()
+            .map(|edge| {
+                let mut visited = HashSet::new();
+                let mut inputs = vec![];
+                for input in edge.all_inputs() {
+                    self.expand_phony(input, &phony, &mut visited, &mut inputs);
+                }
+                inputs
+            })
+            .collect()
+    }
+
+    fn expand_phony<'a>(
+        &self,
+        path: &'a String,
+        phony: &HashMap<&String, &'a NinjaEdge>,
+        visited: &mut HashSet<&'a String>,
+        inputs: &mut Vec<String>,
+    ) {
+        if !visited.insert(path) {
+            return;
+        }
+        match phony.get(path) {
+            Some(edge) if edge.all_inputs().next().is_some() => {
+                for input in edge.all_inputs() {
+                    self.expand_phony(input, phony, visited, inputs);
+                }
+            }
+            // phony target without inputs is used for files which might not exist
+            Some(_) => {
+                if self.build_dir.join(path).is_file() {
+                    inputs.push(path.clone());
+                }
+            }
+            None => inputs.push(path.clone()),
+        }
+    }
+
+    /// Indices of edges to push, producers before consumers, phony and generator edges are skipped
+    fn topological_order(&self, deps: &[Vec<String>]) -> Result<Vec<usize>, anyhow::Error> {
+        let producers: HashMap<&String, usize> = self
+            .edges
+            .iter()
+            .enumerate()
+            .filter(|(_, edge)| edge.rule != "phony" && !edge.generator)
+            .flat_map(|(i, edge)| edge.all_outputs().map(move |x| (x, i)))
+            .collect();
+        let mut order = vec![];
+        // 0: not visited, 1: visiting, 2: done
+        let mut state = vec![0; self.edges.len()];
+        for i in producers.values().cloned().sorted_unstable().dedup() {
+            self.visit(i, deps, &producers, &mut state, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit(
+        &self,
+        i: usize,
+        deps: &[Vec<String>],
+        producers: &HashMap<&String, usize>,
+        state: &mut Vec<u8>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), anyhow::Error> {
+        match state[i] {
+            1 => bail!("dependency cycle: {}", self.edges[i].location),
+            2 => return Ok(()),
+            _ => {}
+        }
+        state[i] = 1;
+        for dep in &deps[i] {
+            if let Some(producer) = producers.get(dep) {
+                self.visit(*producer, deps, producers, state, order)?;
+            }
+        }
+        state[i] = 2;
+        order.push(i);
+        Ok(())
+    }
+}
+
+/// Variables of a build edge: edge bindings, rule bindings, file scope
+struct EdgeEnv<'a> {
+    scope: &'a Scope,
+    rule: &'a HashMap<String, String>,
+    bindings: &'a HashMap<String, String>,
+    inputs: &'a [String],
+    outputs: &'a [String],
+}
+
+impl EdgeEnv<'_> {
+    fn lookup(&self, name: &str, depth: usize) -> Result<String, anyhow::Error> {
+        match name {
+            "in" => Ok(self.inputs.iter().map(|x| shell_escape(x)).join(" ")),
+            "in_newline" => Ok(self.inputs.iter().map(|x| shell_escape(x)).join("\n")),
+            "out" => Ok(self.outputs.iter().map(|x| shell_escape(x)).join(" ")),
+            _ => {
+                if let Some(x) = self.bindings.get(name) {
+                    Ok(x.clone())
+                } else if let Some(x) = self.rule.get(name) {
+                    if depth > MAX_EVALUATION_DEPTH {
+                        bail!("cycle in rule variable: {name}");
+                    }
+                    evaluate(x, &|x| self.lookup(x, depth + 1))
+                } else {
+                    self.scope.lookup(name)
+                }
+            }
+        }
+    }
+}
+
+/// Write the response file with a task, paths of inputs are mapped like in the command line
+fn push_rspfile(
+    scheduler: &mut Scheduler,
+    name: &str,
+    rspfile: &String,
+    content: &str,
+    path_map: &HashMap<String, String>,
+) -> Result<PathBuf, anyhow::Error> {
+    let lines = content
+        .lines()
+        .map(|line| line.split(' ').map(|x| map_word(x, path_map)).join(" "))
+        .collect_vec();
+    let mut args = vec![
+        config::EXECUTABLE.to_string(),
+        "task".into(),
+        "write-file".into(),
+        rspfile.clone(),
+    ];
+    args.extend(lines.iter().cloned());
+    let mut builder = CommandBuilder::new(format!("{name}.rsp"), args);
+    let output = builder.output(rspfile, scheduler)?;
+    let path = output.clone();
+    builder.task_executor(Arc::new(move || {
+        tasks::write_file(output.clone(), lines.clone())
+    }));
+    scheduler.push(builder)?;
+    Ok(path)
+}
+
+fn logical_lines(text: &str) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut text_line = String::new();
+    let mut number = 1;
+    let mut start = 1;
+    let mut push = |start: usize, text_line: &mut String| {
+        let text = text_line.trim_end();
+        let trimmed = text.trim_start();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            lines.push(Line {
+                number: start,
+                indented: trimmed.len() != text.len(),
+                text: trimmed.into(),
+            });
+        }
+        text_line.clear();
+    };
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => match chars.next() {
+                Some('\n') => {
+                    number += 1;
+                    while chars.next_if(|x| *x == ' ').is_some() {}
+                }
+                Some(x) => {
+                    text_line.push('$');
+                    text_line.push(x);
+                }
+                None => text_line.push('$'),
+            },
+            '\n' => {
+                push(start, &mut text_line);
+                number += 1;
+                start = number;
+            }
+            _ => text_line.push(c),
+        }
+    }
+    push(start, &mut text_line);
+    lines
+}
+
+fn parse_binding(text: &str) -> Result<(String, String), anyhow::Error> {
+    let (key, value) = text
+        .split_once('=')
+        .with_context(|| format!("expected variable binding: {text}"))?;
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(is_var_char) {
+        bail!("invalid variable name: {key:?}");
+    }
+    Ok((key.into(), value.trim_start().into()))
+}
+
+fn is_var_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+#[derive(Debug, PartialEq)]
+enum BuildToken {
+    /// unevaluated path
+    Path(String),
+    Colon,
+    Pipe,
+    Pipe2,
+    PipeAt,
+}
+
+/// Split a build statement at unescaped spaces, colons and pipes
+fn split_build_line(text: &str) -> Result<Vec<BuildToken>, anyhow::Error> {
+    let mut tokens = vec![];
+    let mut path = String::new();
+    let mut chars = text.chars().peekable();
+    let finish = |path: &mut String, tokens: &mut Vec<BuildToken>| {
+        if !path.is_empty() {
+            tokens.push(BuildToken::Path(std::mem::take(path)));
+        }
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => {
+                path.push('$');
+                path.push(chars.next().context("unexpected end after $")?);
+            }
+            ' ' => finish(&mut path, &mut tokens),
+            ':' => {
+                finish(&mut path, &mut tokens);
+                tokens.push(BuildToken::Colon);
+            }
+            '|' => {
+                finish(&mut path, &mut tokens);
+                tokens.push(if chars.next_if_eq(&'|').is_some() {
+                    BuildToken::Pipe2
+                } else if chars.next_if_eq(&'@').is_some() {
+                    BuildToken::PipeAt
+                } else {
+                    BuildToken::Pipe
+                });
+            }
+            _ => path.push(c),
+        }
+    }
+    finish(&mut path, &mut tokens);
+    Ok(tokens)
+}
+
+/// Expand variables and escapes of a ninja string
+fn evaluate(
+    text: &str,
+    lookup: &dyn Fn(&str) -> Result<String, anyhow::Error>,
+) -> Result<String, anyhow::Error> {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(x @ ('$' | ' ' | ':')) => result.push(x),
+            Some('{') => {
+                let name: String = chars.by_ref().take_while(|x| *x != '}').collect();
+                result += &lookup(&name)?;
+            }
+            Some(x) if is_var_char(x) => {
+                let mut name = x.to_string();
+                while let Some(x) = chars.next_if(|x| is_var_char(*x)) {
+                    name.push(x);
+                }
+                result += &lookup(&name)?;
+            }
+            x => bail!("bad $-escape: ${}", x.map(String::from).unwrap_or_default()),
+        }
+    }
+    Ok(result)
+}
+
+/// Split a word into an option and an attached path, e.g. `-o` and `a.o` of `-oa.o`
+fn joined_path<'a>(word: &'a str, path_map: &HashMap<String, String>) -> Option<(&'a str, String)> {
+    path_map
+        .iter()
+        .filter_map(|(path, mapped)| {
+            word.strip_suffix(path.as_str())
+                .filter(|x| (x.starts_with('-') && x.len() > 1) || *x == "@")
+                .map(|prefix| (prefix, path.len(), mapped))
+        })
+        .max_by_key(|(_, len, _)| *len)
+        .map(|(prefix, _, mapped)| (prefix, mapped.clone()))
+}
+
+/// Map a path or a path attached to an option like `-o$out`, `--out=$out` or `@$out.rsp`
+fn map_word(word: &str, path_map: &HashMap<String, String>) -> String {
+    if let Some(x) = path_map.get(word) {
+        x.clone()
+    } else if let Some((prefix, mapped)) = joined_path(word, path_map) {
+        format!("{prefix}{mapped}")
+    } else {
+        word.into()
+    }
+}
+
+/// Quote a path for the shell like ninja does for $in and $out
+fn shell_escape(x: &str) -> String {
+    if !x.is_empty()
+        && x.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_+-./".contains(c))
+    {
+        x.into()
+    } else {
+        format!("'{}'", x.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    fn load(files: &[(&str, &str)]) -> NinjaManifest {
+        let dir = TempDir::new().unwrap();
+        for (name, text) in files {
+            fs::write(dir.child(name), text).unwrap();
+        }
+        NinjaManifest::load(dir.path(), files[0].0).unwrap()
+    }
+
+    #[test]
+    fn variables_and_rules() {
+        let manifest = load(&[(
+            "build.ninja",
+            r#"# comment
+cflags = -O2
+cflags = $cflags -Wall
+rule cc
+  command = cc $cflags -MF $out.d -o $out -c $in
+  depfile = $out.d
+  description = CC $out
+build ./obj/a.o: cc src/../a.c | a.h || gen
+  cflags = $cflags -g
+build obj/b.o | obj/b.map: cc $
+    b.c
+"#,
+        )]);
+        assert_eq!(manifest.edges.len(), 2);
+        let a = &manifest.edges[0];
+        assert_eq!(a.outputs, ["obj/a.o"]);
+        assert_eq!(a.inputs, ["a.c"]);
+        assert_eq!(a.implicit_inputs, ["a.h"]);
+        assert_eq!(a.order_only_inputs, ["gen"]);
+        assert_eq!(a.command, "cc -O2 -Wall -g -MF obj/a.o.d -o obj/a.o -c a.c");
+        assert_eq!(a.depfile, Some("obj/a.o.d".into()));
+        assert!(a.location.ends_with("build.ninja:8"));
+        let b = &manifest.edges[1];
+        assert_eq!(b.implicit_outputs, ["obj/b.map"]);
+        assert_eq!(b.command, "cc -O2 -Wall -MF obj/b.o.d -o obj/b.o -c b.c");
+    }
+
+    #[test]
+    fn include_subninja_pools_rspfiles() {
+        let manifest = load(&[
+            (
+                "build.ninja",
+                "x = 1\ninclude rules.ninja\nsubninja sub.ninja\npool link\n  depth = 2\nbuild b: r a\n",
+            ),
+            (
+                "rules.ninja",
+                "rule r\n  command = r $x @$out.rsp\n  rspfile = $out.rsp\n  rspfile_content = $in_newline\n  pool = link\n",
+            ),
+            ("sub.ninja", "x = 2\nbuild c: r a b\n"),
+        ]);
+        assert_eq!(manifest.pools, [("link".to_string(), 2)]);
+        let c = &manifest.edges[0];
+        assert_eq!(c.command, "r 2 @c.rsp");
+        assert_eq!(c.rspfile, Some("c.rsp".into()));
+        assert_eq!(c.rspfile_content, "a\nb");
+        assert_eq!(c.pool, Some("link".into()));
+        // variables of subninja do not leak into the parent scope
+        assert_eq!(manifest.edges[1].command, "r 1 @b.rsp");
+    }
+
+    #[test]
+    fn phony_and_order() {
+        let manifest = load(&[(
+            "build.ninja",
+            "rule r\n  command = r\nbuild app: r lib all_objs\nbuild all_objs: phony a.o b.o\nbuild a.o b.o: r a.c\nbuild lib: r\nbuild build.ninja: r CMakeLists.txt\n  generator = 1\n",
+        )]);
+        let deps = manifest.expanded_inputs();
+        assert_eq!(deps[0], ["lib", "a.o", "b.o"]);
+        let order = manifest.topological_order(&deps).unwrap();
+        assert_eq!(order.len(), 3);
+        assert_eq!(*order.last().unwrap(), 0);
+    }
+
+    #[test]
+    fn errors() {
+        let dir = TempDir::new().unwrap();
+        let error = |text: &str| {
+            fs::write(dir.child("build.ninja"), text).unwrap();
+            format!(
+                "{:#}",
+                NinjaManifest::load(dir.path(), "build.ninja").unwrap_err()
+            )
+        };
+        assert!(error("\nbuild a: missing b\n").ends_with("build.ninja:2: unknown rule: missing"));
+        assert!(error("rule r\n  description = x\n").ends_with("rule r has no command"));
+        assert!(error("x = $!\n").ends_with("bad $-escape: $!"));
+    }
+
+    #[test]
+    fn paths() {
+        let path_map = HashMap::from([
+            ("a.o".to_string(), "../razel-out/b/a.o".to_string()),
+            ("oa.o".to_string(), "../razel-out/b/oa.o".to_string()),
+        ]);
+        assert_eq!(map_word("a.o", &path_map), "../razel-out/b/a.o");
+        assert_eq!(map_word("-oa.o", &path_map), "-o../razel-out/b/a.o");
+        assert_eq!(map_word("--out=a.o", &path_map), "--out=../razel-out/b/a.o");
+        assert_eq!(map_word("@oa.o", &path_map), "@../razel-out/b/oa.o");
+        assert_eq!(map_word("xa.o", &path_map), "xa.o");
+        assert_eq!(shell_escape("a/b.c"), "a/b.c");
+        assert_eq!(shell_escape("a b'c"), "'a b'\\''c'");
+    }
+}
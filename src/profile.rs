@@ -0,0 +1,193 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::json;
+
+/// Thread id of the scheduler, commands are executed on worker threads 1..
+pub static SCHEDULER_TID: usize = 0;
+
+/// Records spans to be written as Chrome trace-event file for chrome://tracing or Perfetto
+///
+/// Cloning is cheap, all clones record into the same profile. Recording is a no-op if disabled.
+#[derive(Clone, Default)]
+pub struct Profile(Option<Arc<ProfileData>>);
+
+struct ProfileData {
+    start: Instant,
+    events: Mutex<Vec<TraceEvent>>,
+    /// used worker thread ids, index 0 is the scheduler
+    tids: Mutex<Vec<bool>>,
+}
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    /// start in microseconds
+    ts: u64,
+    /// duration in microseconds
+    dur: u64,
+    pid: u32,
+    tid: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self(Some(Arc::new(ProfileData {
+            start: Instant::now(),
+            events: Default::default(),
+            tids: Mutex::new(vec![true]),
+        })))
+    }
+
+    /// Record a span from start until now
+    pub fn span(&self, name: &str, cat: &'static str, tid: usize, start: Instant) {
+        self.span_with_args(name, cat, tid, start, None);
+    }
+
+    /// Record a span from start until now with additional info shown for the span
+    pub fn span_with_args(
+        &self,
+        name: &str,
+        cat: &'static str,
+        tid: usize,
+        start: Instant,
+        args: Option<serde_json::Value>,
+    ) {
+        if let Some(data) = &self.0 {
+            let end = Instant::now();
+            let event = TraceEvent {
+                name: name.into(),
+                cat,
+                ph: "X",
+                ts: start.saturating_duration_since(data.start).as_micros() as u64,
+                dur: end.saturating_duration_since(start).as_micros() as u64,
+                pid: std::process::id(),
+                tid,
+                args,
+            };
+            data.events.lock().unwrap().push(event);
+        }
+    }
+
+    /// Acquire the lowest free worker thread id, to show concurrent commands on separate lanes
+    pub fn thread(&self) -> ProfileThread {
+        let tid = if let Some(data) = &self.0 {
+            let mut tids = data.tids.lock().unwrap();
+            if let Some(tid) = tids.iter().position(|x| !x) {
+                tids[tid] = true;
+                tid
+            } else {
+                tids.push(true);
+                tids.len() - 1
+            }
+        } else {
+            SCHEDULER_TID
+        };
+        ProfileThread {
+            profile: self.clone(),
+            tid,
+        }
+    }
+
+    /// Write the recorded spans as Chrome trace-event file
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let data = match &self.0 {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let pid = std::process::id();
+        let thread_count = data.tids.lock().unwrap().len();
+        let mut events = (0..thread_count)
+            .map(|tid| {
+                let name = if tid == SCHEDULER_TID {
+                    "scheduler".into()
+                } else {
+                    format!("worker {tid}")
+                };
+                json!({"name": "thread_name", "ph": "M", "pid": pid, "tid": tid, "args": {"name": name}})
+            })
+            .collect::<Vec<_>>();
+        for event in data.events.lock().unwrap().iter() {
+            events.push(serde_json::to_value(event)?);
+        }
+        let json = json!({"traceEvents": events, "displayTimeUnit": "ms"});
+        fs::write(path, serde_json::to_string(&json)?)
+            .with_context(|| format!("failed to write profile: {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// Worker thread of a profile, released on drop
+pub struct ProfileThread {
+    profile: Profile,
+    tid: usize,
+}
+
+impl ProfileThread {
+    pub fn span(&self, name: &str, cat: &'static str, start: Instant) {
+        self.profile.span(name, cat, self.tid, start);
+    }
+
+    pub fn span_with_args(
+        &self,
+        name: &str,
+        cat: &'static str,
+        start: Instant,
+        args: Option<serde_json::Value>,
+    ) {
+        self.profile
+            .span_with_args(name, cat, self.tid, start, args);
+    }
+}
+
+impl Drop for ProfileThread {
+    fn drop(&mut self) {
+        if let Some(data) = &self.profile.0 {
+            data.tids.lock().unwrap()[self.tid] = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn threads_are_reused() {
+        let profile = Profile::new();
+        let thread1 = profile.thread();
+        let thread2 = profile.thread();
+        assert_eq!(thread1.tid, 1);
+        assert_eq!(thread2.tid, 2);
+        drop(thread1);
+        assert_eq!(profile.thread().tid, 1);
+        assert_eq!(Profile::default().thread().tid, SCHEDULER_TID);
+    }
+
+    #[test]
+    fn write() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.child("profile.json");
+        let profile = Profile::new();
+        profile.thread().span("execute", "command", Instant::now());
+        profile.write(&path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let events = json["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[1]["args"]["name"], "worker 1");
+        assert_eq!(events[2]["name"], "execute");
+        assert_eq!(events[2]["ph"], "X");
+        assert_eq!(events[2]["tid"], 1);
+    }
+}
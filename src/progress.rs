@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+use crate::executors::ExecutionResult;
+use crate::CommandId;
+
+/// Max number of running commands shown in the terminal
+static MAX_RUNNING_LINES: usize = 10;
+/// Max width of a line in the terminal, longer lines are truncated to keep redrawing simple
+static MAX_LINE_WIDTH: usize = 100;
+/// Interval to log the status if stderr is not a terminal
+static LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Running {
+    id: CommandId,
+    name: String,
+    start: Instant,
+    estimate: Option<Duration>,
+}
+
+/// Shows the progress of running commands.
+///
+/// If stderr is a terminal, a status block with the running commands is redrawn periodically.
+/// Otherwise plain log lines are written.
+pub struct Progress {
+    tty: bool,
+    parallelism: usize,
+    total: usize,
+    succeeded: usize,
+    cached: usize,
+    failed: usize,
+    running: Vec<Running>,
+    /// estimated durations of all commands, from the execution history
+    estimates: HashMap<CommandId, Duration>,
+    /// estimated durations of commands not yet started, from the execution history
+    known_remaining: Duration,
+    known_remaining_count: usize,
+    /// number of commands not yet started without estimation
+    unknown_remaining: usize,
+    /// sum and count of durations of executed commands, to estimate unknown commands
+    executed_duration: Duration,
+    executed_count: usize,
+    /// number of lines to clear before redrawing
+    drawn_lines: usize,
+    last_log: Instant,
+}
+
+impl Progress {
+    pub const INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new(parallelism: usize) -> Self {
+        Self {
+            tty: std::io::stderr().is_terminal(),
+            parallelism,
+            total: 0,
+            succeeded: 0,
+            cached: 0,
+            failed: 0,
+            running: vec![],
+            estimates: Default::default(),
+            known_remaining: Duration::ZERO,
+            known_remaining_count: 0,
+            unknown_remaining: 0,
+            executed_duration: Duration::ZERO,
+            executed_count: 0,
+            drawn_lines: 0,
+            last_log: Instant::now(),
+        }
+    }
+
+    /// Register a command which will be executed, with its estimated duration
+    pub fn add(&mut self, id: CommandId, estimate: Option<Duration>) {
+        self.total += 1;
+        if let Some(x) = estimate {
+            self.estimates.insert(id, x);
+            self.known_remaining += x;
+            self.known_remaining_count += 1;
+        } else {
+            self.unknown_remaining += 1;
+        }
+    }
+
+    pub fn on_start(&mut self, id: CommandId, name: &str) {
+        let estimate = self.estimates.get(&id).cloned();
+        if let Some(x) = estimate {
+            self.known_remaining = self.known_remaining.saturating_sub(x);
+            self.known_remaining_count -= 1;
+        } else {
+            self.unknown_remaining -= 1;
+        }
+        self.running.push(Running {
+            id,
+            name: name.into(),
+            start: Instant::now(),
+            estimate,
+        });
+        if !self.tty {
+            info!("Execute {}", name);
+        }
+    }
+
+    pub fn on_finished(&mut self, id: CommandId, name: &str, execution_result: &ExecutionResult) {
+        if let Some(i) = self.running.iter().position(|x| x.id == id) {
+            self.running.swap_remove(i);
+        }
+        if let Some(duration) = execution_result.duration {
+            self.executed_duration += duration;
+            self.executed_count += 1;
+        }
+        if execution_result.success() {
+            self.succeeded += 1;
+            if execution_result.cache_hit {
+                self.cached += 1;
+            }
+            if !self.tty {
+                info!("Success {}: {:?}", name, execution_result);
+            }
+        } else {
+            self.failed += 1;
+            self.clear();
+            error!("Error  {}: {:?}", name, execution_result);
+        }
+    }
+
+    /// Redraw the status block or log the status periodically
+    pub fn tick(&mut self) {
+        if self.tty {
+            self.draw();
+        } else if self.last_log.elapsed() >= LOG_INTERVAL {
+            self.last_log = Instant::now();
+            info!("{}", self.status_line());
+        }
+    }
+
+    /// Remove the status block from the terminal
+    pub fn finish(&mut self) {
+        self.clear();
+    }
+
+    /// Estimated time until all commands are finished, None if nothing is known yet
+    pub fn eta(&self) -> Option<Duration> {
+        let mean = if self.executed_count != 0 {
+            self.executed_duration / self.executed_count as u32
+        } else if self.known_remaining_count != 0 {
+            self.known_remaining / self.known_remaining_count as u32
+        } else {
+            return None;
+        };
+        let running: Duration = self
+            .running
+            .iter()
+            .map(|x| x.estimate.unwrap_or(mean).saturating_sub(x.start.elapsed()))
+            .sum();
+        let work = self.known_remaining + mean * self.unknown_remaining as u32 + running;
+        Some(work / self.parallelism as u32)
+    }
+
+    fn status_line(&self) -> String {
+        let done = self.succeeded + self.failed;
+        let remaining = self.total - done - self.running.len();
+        let mut line = format!(
+            "[{}/{}] {} succeeded ({} cached), {} failed, {} running, {} remaining",
+            done,
+            self.total,
+            self.succeeded,
+            self.cached,
+            self.failed,
+            self.running.len(),
+            remaining
+        );
+        if let Some(eta) = self.eta() {
+            line += &format!(", ETA {}", format_duration(eta));
+        }
+        line
+    }
+
+    fn draw(&mut self) {
+        let mut lines = vec![self.status_line()];
+        for running in self.running.iter().take(MAX_RUNNING_LINES) {
+            lines.push(format!(
+                "  {} ({})",
+                running.name,
+                format_duration(running.start.elapsed())
+            ));
+        }
+        if self.running.len() > MAX_RUNNING_LINES {
+            lines.push(format!(
+                "  ... {} more",
+                self.running.len() - MAX_RUNNING_LINES
+            ));
+        }
+        let mut output = self.clear_sequence();
+        for line in &lines {
+            output.extend(line.chars().take(MAX_LINE_WIDTH));
+            output.push('\n');
+        }
+        eprint!("{}", output);
+        self.drawn_lines = lines.len();
+    }
+
+    fn clear(&mut self) {
+        if self.drawn_lines != 0 {
+            eprint!("{}", self.clear_sequence());
+            self.drawn_lines = 0;
+        }
+    }
+
+    /// ANSI sequence to move the cursor to the start of the status block and clear it
+    fn clear_sequence(&self) -> String {
+        if self.drawn_lines == 0 {
+            String::new()
+        } else {
+            format!("\x1b[{}F\x1b[J", self.drawn_lines)
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{:.1}s", duration.as_secs_f32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::executors::ExecutionStatus;
+    use crate::{CommandBuilder, Scheduler};
+
+    use super::*;
+
+    /// Push commands to get distinct ids like in a real run
+    fn push_commands(count: usize) -> Vec<CommandId> {
+        let mut scheduler = Scheduler::new();
+        (0..count)
+            .map(|i| {
+                let mut builder = CommandBuilder::new(format!("command-{i}"), vec![]);
+                builder
+                    .custom_command_executor("true".into(), Default::default(), &mut scheduler)
+                    .unwrap();
+                scheduler.push(builder).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn eta() {
+        let ids = push_commands(3);
+        let mut progress = Progress::new(2);
+        progress.add(ids[0], Some(Duration::from_secs(4)));
+        progress.add(ids[1], Some(Duration::from_secs(2)));
+        progress.add(ids[2], None);
+        // mean of estimations is used for commands without history
+        assert_eq!(progress.eta(), Some(Duration::from_millis(4500)));
+        assert!(progress.status_line().ends_with("3 remaining, ETA 4.5s"));
+    }
+
+    #[test]
+    fn eta_unknown() {
+        let ids = push_commands(2);
+        let mut progress = Progress::new(2);
+        progress.add(ids[0], None);
+        progress.add(ids[1], None);
+        assert_eq!(progress.eta(), None);
+        progress.on_start(ids[0], "command-0");
+        let result = ExecutionResult {
+            status: ExecutionStatus::Success,
+            exit_code: Some(0),
+            duration: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        progress.on_finished(ids[0], "command-0", &result);
+        // mean of executed durations is used for commands without history
+        assert_eq!(progress.eta(), Some(Duration::from_millis(500)));
+        assert!(progress
+            .status_line()
+            .starts_with("[1/2] 1 succeeded (0 cached), 0 failed, 0 running, 1 remaining"));
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(format_duration(Duration::from_millis(4200)), "4.2s");
+        assert_eq!(format_duration(Duration::from_secs(83)), "1m23s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m");
+    }
+}
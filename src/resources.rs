@@ -0,0 +1,448 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::bail;
+use serde::{Deserialize, Deserializer};
+
+/// Resources requested by a command
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ResourceRequest {
+    /// number of CPU slots, e.g. threads used by the command
+    #[serde(
+        default = "ResourceRequest::default_cpus",
+        deserialize_with = "ResourceRequest::deserialize_cpus"
+    )]
+    pub cpus: usize,
+    /// peak memory in MiB
+    #[serde(default)]
+    pub memory: u64,
+    /// run the command alone, e.g. for real-time critical tests
+    #[serde(default)]
+    pub exclusive: bool,
+    /// name of a concurrency pool, defaults to the pool of the executable
+    #[serde(default)]
+    pub pool: Option<String>,
+    /// name of a scheduling lane, e.g. "network" for commands which do not use CPU slots
+    #[serde(default)]
+    pub lane: Option<String>,
+    /// names of resources used exclusively, e.g. a database used by tests
+    #[serde(default)]
+    pub locks: Vec<String>,
+}
+
+impl ResourceRequest {
+    fn default_cpus() -> usize {
+        1
+    }
+
+    /// A command without CPU slots would bypass the CPU limit, lanes are meant for that
+    fn deserialize_cpus<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+        match usize::deserialize(deserializer)? {
+            0 => Err(serde::de::Error::custom("cpus must be greater than 0")),
+            x => Ok(x),
+        }
+    }
+}
+
+impl Default for ResourceRequest {
+    fn default() -> Self {
+        Self {
+            cpus: Self::default_cpus(),
+            memory: 0,
+            exclusive: false,
+            pool: None,
+            lane: None,
+            locks: vec![],
+        }
+    }
+}
+
+/// Limits the number of concurrently running commands, e.g. for license-limited tools
+#[derive(Debug)]
+struct Pool {
+    depth: usize,
+    /// commands with these executables join the pool automatically
+    executables: Vec<String>,
+    running: usize,
+}
+
+/// Commands in a lane are limited by the lane instead of by CPU slots, e.g. for I/O-bound commands
+#[derive(Debug)]
+struct Lane {
+    limit: usize,
+    running: usize,
+}
+
+/// Lane which is always available
+pub static NETWORK_LANE: &str = "network";
+static NETWORK_LANE_LIMIT: usize = 8;
+
+/// Reason why requested resources are not available
+#[derive(Debug, PartialEq)]
+pub enum Blocked {
+    /// CPU slots, memory or exclusivity: later commands must wait to not starve this one
+    Machine,
+    /// a pool, lane or lock: later commands not using it may still start
+    Named,
+}
+
+/// Tracks the resources of the machine used by running commands
+#[derive(Debug)]
+pub struct Resources {
+    cpus: usize,
+    /// available memory in MiB, None if unknown
+    memory: Option<u64>,
+    used_cpus: usize,
+    used_memory: u64,
+    running: usize,
+    exclusive_running: bool,
+    pools: HashMap<String, Pool>,
+    lanes: HashMap<String, Lane>,
+    /// locks held by running commands
+    locks: HashSet<String>,
+}
+
+impl Resources {
+    pub fn new(cpus: usize, memory: Option<u64>) -> Self {
+        assert!(cpus > 0);
+        Self {
+            cpus,
+            memory,
+            used_cpus: 0,
+            used_memory: 0,
+            running: 0,
+            exclusive_running: false,
+            pools: Default::default(),
+            locks: Default::default(),
+            lanes: HashMap::from([(
+                NETWORK_LANE.into(),
+                Lane {
+                    limit: NETWORK_LANE_LIMIT,
+                    running: 0,
+                },
+            )]),
+        }
+    }
+
+    /// Declare a lane or change its limit
+    pub fn set_lane(&mut self, name: String, limit: usize) -> Result<(), anyhow::Error> {
+        if limit == 0 {
+            bail!("limit of lane {name:?} must be greater than 0");
+        }
+        self.lanes
+            .entry(name)
+            .or_insert(Lane { limit, running: 0 })
+            .limit = limit;
+        Ok(())
+    }
+
+    pub fn has_lane(&self, name: &str) -> bool {
+        self.lanes.contains_key(name)
+    }
+
+    /// Declare a pool, commands join it by name or by executable
+    pub fn add_pool(
+        &mut self,
+        name: String,
+        depth: usize,
+        executables: Vec<String>,
+    ) -> Result<(), anyhow::Error> {
+        if depth == 0 {
+            bail!("depth of pool {name:?} must be greater than 0");
+        }
+        if self.pools.contains_key(&name) {
+            bail!("pool {name:?} is declared multiple times");
+        }
+        if let Some(x) = executables
+            .iter()
+            .find(|x| self.pool_for_executable(x).is_some())
+        {
+            bail!("executable {x:?} is already used by another pool");
+        }
+        self.pools.insert(
+            name,
+            Pool {
+                depth,
+                executables,
+                running: 0,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn has_pool(&self, name: &str) -> bool {
+        self.pools.contains_key(name)
+    }
+
+    /// Returns the pool for an executable, matched by path or by file name
+    pub fn pool_for_executable(&self, executable: &str) -> Option<&String> {
+        let file_name = Path::new(executable).file_name();
+        self.pools
+            .iter()
+            .find(|(_, pool)| {
+                pool.executables
+                    .iter()
+                    .any(|x| x == executable || file_name == Some(x.as_ref()))
+            })
+            .map(|(name, _)| name)
+    }
+
+    pub fn memory(&self) -> Option<u64> {
+        self.memory
+    }
+
+    /// Reserve the requested resources if they are available.
+    ///
+    /// Requests exceeding the machine are limited to it, to run those commands alone.
+    pub fn try_acquire(&mut self, request: &ResourceRequest) -> Result<(), Blocked> {
+        if self.exclusive_running || (request.exclusive && self.running != 0) {
+            return Err(Blocked::Machine);
+        }
+        if let Some(lane) = request.lane.as_ref().map(|x| &self.lanes[x]) {
+            if lane.running == lane.limit {
+                return Err(Blocked::Named);
+            }
+        }
+        if request.locks.iter().any(|x| self.locks.contains(x)) {
+            return Err(Blocked::Named);
+        }
+        if let Some(pool) = request.pool.as_ref().map(|x| &self.pools[x]) {
+            if pool.running == pool.depth {
+                return Err(Blocked::Named);
+            }
+        }
+        let cpus = self.limit_cpus(request);
+        let memory = self.limit_memory(request);
+        if self.used_cpus + cpus > self.cpus {
+            return Err(Blocked::Machine);
+        }
+        if let Some(available) = self.memory {
+            if self.used_memory + memory > available {
+                return Err(Blocked::Machine);
+            }
+        }
+        if let Some(pool) = &request.pool {
+            self.pools.get_mut(pool).unwrap().running += 1;
+        }
+        if let Some(lane) = &request.lane {
+            self.lanes.get_mut(lane).unwrap().running += 1;
+        }
+        self.locks.extend(request.locks.iter().cloned());
+        self.used_cpus += cpus;
+        self.used_memory += memory;
+        self.running += 1;
+        self.exclusive_running = request.exclusive;
+        Ok(())
+    }
+
+    /// Release resources reserved by try_acquire()
+    pub fn release(&mut self, request: &ResourceRequest) {
+        self.used_cpus -= self.limit_cpus(request);
+        self.used_memory -= self.limit_memory(request);
+        self.running -= 1;
+        if let Some(pool) = &request.pool {
+            self.pools.get_mut(pool).unwrap().running -= 1;
+        }
+        if let Some(lane) = &request.lane {
+            self.lanes.get_mut(lane).unwrap().running -= 1;
+        }
+        if request.exclusive {
+            self.exclusive_running = false;
+        }
+        for lock in &request.locks {
+            self.locks.remove(lock);
+        }
+    }
+
+    /// Commands in a lane do not use CPU slots
+    fn limit_cpus(&self, request: &ResourceRequest) -> usize {
+        if request.lane.is_some() {
+            0
+        } else {
+            request.cpus.min(self.cpus)
+        }
+    }
+
+    fn limit_memory(&self, request: &ResourceRequest) -> u64 {
+        self.memory.map_or(0, |x| request.memory.min(x))
+    }
+}
+
+/// Returns the total memory of the machine in MiB
+#[cfg(target_os = "linux")]
+pub fn total_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kib: u64 = meminfo
+        .lines()
+        .find_map(|x| x.strip_prefix("MemTotal:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib / 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn total_memory() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(cpus: usize, memory: u64, exclusive: bool) -> ResourceRequest {
+        ResourceRequest {
+            cpus,
+            memory,
+            exclusive,
+            pool: None,
+            lane: None,
+            locks: vec![],
+        }
+    }
+
+    #[test]
+    fn cpus() {
+        let mut resources = Resources::new(4, None);
+        assert!(resources.try_acquire(&request(3, 0, false)).is_ok());
+        assert_eq!(
+            resources.try_acquire(&request(2, 0, false)),
+            Err(Blocked::Machine)
+        );
+        assert!(resources.try_acquire(&request(1, 0, false)).is_ok());
+        resources.release(&request(3, 0, false));
+        assert!(resources.try_acquire(&request(2, 0, false)).is_ok());
+    }
+
+    #[test]
+    fn cpus_must_not_be_zero() {
+        let parse = |json: &str| serde_json::from_str::<ResourceRequest>(json);
+        assert_eq!(parse("{}").unwrap().cpus, 1);
+        assert_eq!(parse(r#"{"cpus": 2}"#).unwrap().cpus, 2);
+        assert!(parse(r#"{"cpus": 0}"#).is_err());
+    }
+
+    #[test]
+    fn memory() {
+        let mut resources = Resources::new(4, Some(1000));
+        assert!(resources.try_acquire(&request(1, 800, false)).is_ok());
+        assert_eq!(
+            resources.try_acquire(&request(1, 300, false)),
+            Err(Blocked::Machine)
+        );
+        assert!(resources.try_acquire(&request(1, 200, false)).is_ok());
+        resources.release(&request(1, 800, false));
+        assert!(resources.try_acquire(&request(1, 300, false)).is_ok());
+    }
+
+    #[test]
+    fn request_exceeding_machine_runs_alone() {
+        let mut resources = Resources::new(4, Some(1000));
+        assert!(resources.try_acquire(&request(8, 2000, false)).is_ok());
+        assert_eq!(
+            resources.try_acquire(&request(1, 0, false)),
+            Err(Blocked::Machine)
+        );
+        resources.release(&request(8, 2000, false));
+        assert!(resources.try_acquire(&request(1, 0, false)).is_ok());
+    }
+
+    #[test]
+    fn exclusive() {
+        let mut resources = Resources::new(4, None);
+        assert!(resources.try_acquire(&request(1, 0, false)).is_ok());
+        assert_eq!(
+            resources.try_acquire(&request(1, 0, true)),
+            Err(Blocked::Machine)
+        );
+        resources.release(&request(1, 0, false));
+        assert!(resources.try_acquire(&request(1, 0, true)).is_ok());
+        assert_eq!(
+            resources.try_acquire(&request(1, 0, false)),
+            Err(Blocked::Machine)
+        );
+        resources.release(&request(1, 0, true));
+        assert!(resources.try_acquire(&request(1, 0, false)).is_ok());
+    }
+
+    #[test]
+    fn pool() {
+        let mut resources = Resources::new(4, None);
+        resources
+            .add_pool("license".into(), 2, vec!["vsim".into()])
+            .unwrap();
+        let in_pool = ResourceRequest {
+            pool: Some("license".into()),
+            ..Default::default()
+        };
+        assert!(resources.try_acquire(&in_pool).is_ok());
+        assert!(resources.try_acquire(&in_pool).is_ok());
+        assert_eq!(resources.try_acquire(&in_pool), Err(Blocked::Named));
+        assert!(resources.try_acquire(&request(1, 0, false)).is_ok());
+        resources.release(&in_pool);
+        assert!(resources.try_acquire(&in_pool).is_ok());
+    }
+
+    #[test]
+    fn pool_for_executable() {
+        let mut resources = Resources::new(4, None);
+        resources
+            .add_pool(
+                "license".into(),
+                1,
+                vec!["vsim".into(), "tools/run.sh".into()],
+            )
+            .unwrap();
+        assert_eq!(
+            resources.pool_for_executable("/opt/bin/vsim").unwrap(),
+            "license"
+        );
+        assert_eq!(
+            resources.pool_for_executable("tools/run.sh").unwrap(),
+            "license"
+        );
+        assert!(resources.pool_for_executable("cmake").is_none());
+        assert!(resources
+            .add_pool("other".into(), 1, vec!["vsim".into()])
+            .is_err());
+        assert!(resources.add_pool("license".into(), 1, vec![]).is_err());
+    }
+
+    #[test]
+    fn lane() {
+        let mut resources = Resources::new(1, None);
+        resources.set_lane(NETWORK_LANE.into(), 2).unwrap();
+        let in_lane = ResourceRequest {
+            lane: Some(NETWORK_LANE.into()),
+            ..Default::default()
+        };
+        assert!(resources.try_acquire(&request(1, 0, false)).is_ok());
+        assert!(resources.try_acquire(&in_lane).is_ok());
+        assert!(resources.try_acquire(&in_lane).is_ok());
+        assert_eq!(resources.try_acquire(&in_lane), Err(Blocked::Named));
+        assert_eq!(
+            resources.try_acquire(&request(1, 0, false)),
+            Err(Blocked::Machine)
+        );
+        resources.release(&in_lane);
+        assert!(resources.try_acquire(&in_lane).is_ok());
+        assert!(resources.set_lane("io".into(), 0).is_err());
+    }
+
+    #[test]
+    fn locks() {
+        let mut resources = Resources::new(4, None);
+        let lock = |x: &[&str]| ResourceRequest {
+            locks: x.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        };
+        assert!(resources.try_acquire(&lock(&["db", "port"])).is_ok());
+        assert_eq!(resources.try_acquire(&lock(&["db"])), Err(Blocked::Named));
+        assert!(resources.try_acquire(&lock(&["gpu"])).is_ok());
+        assert!(resources.try_acquire(&request(1, 0, false)).is_ok());
+        resources.release(&lock(&["db", "port"]));
+        assert!(resources.try_acquire(&lock(&["db"])).is_ok());
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
 
 use anyhow::{bail, Context};
@@ -8,16 +9,22 @@ use log::warn;
 ///
 /// Specifying rules:
 /// * first word is the program
+/// * words without file spec select a subcommand, e.g. `cmake -E copy <in>... <out>`
+/// * a word starting with - followed by a file spec is a named argument
+/// * a file spec can be attached to an option, e.g. `-o<out>`, `--output=<out>` or `-Wl,-Map=<out>`
 /// * only input (<in>) and output (<out>) file arguments need to be specified, other arguments (<>) will be ignored
 /// * multiple file arguments are marked with ...
+/// * rule files contain one rule per line, empty lines and lines starting with # are ignored
 ///
 /// Parsing commands using the rules:
 /// * the first command line argument (program) selects the rule(s)
+/// * of those, the rule with the longest subcommand found in the command line is used
 /// * positional arguments are parsed backwards from the command line
 /// * then named arguments are parsed
 /// * other arguments are ignored
 pub struct Rules {
-    rules: HashMap<String, Rule>,
+    /// rules by program
+    rules: HashMap<String, Vec<Rule>>,
 }
 
 impl Rules {
@@ -29,9 +36,31 @@ impl Rules {
         s
     }
 
+    /// Add a rule, replaces an existing rule for the same program and subcommand
     pub fn add(&mut self, spec: &str) -> Result<(), anyhow::Error> {
         let rule = Rule::new(spec)?;
-        self.rules.insert(rule.executable.clone(), rule);
+        let rules = self.rules.entry(rule.executable.clone()).or_default();
+        rules.retain(|x| x.subcommand != rule.subcommand);
+        rules.push(rule);
+        Ok(())
+    }
+
+    /// Add the rules of a file, see [Rules::add_lines]
+    pub fn add_file(&mut self, file_name: &str) -> Result<(), anyhow::Error> {
+        let text = fs::read_to_string(file_name).with_context(|| file_name.to_string())?;
+        self.add_lines(&text, file_name)
+    }
+
+    /// Add one rule per line, `file_name` is only used for error messages
+    pub fn add_lines(&mut self, text: &str, file_name: &str) -> Result<(), anyhow::Error> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            self.add(line)
+                .with_context(|| format!("{}:{}: {}", file_name, i + 1, line))?;
+        }
         Ok(())
     }
 
@@ -45,8 +74,17 @@ impl Rules {
             .to_str()
             .unwrap()
             .into();
-        if let Some(rule) = self.rules.get(&executable_stem) {
+        let rule = self.rules.get(&executable_stem).and_then(|rules| {
+            rules
+                .iter()
+                .filter(|x| x.matches(command))
+                .max_by_key(|x| x.subcommand.len())
+        });
+        if let Some(rule) = rule {
             Ok(Some(rule.parse_command(command)?))
+        } else if self.rules.contains_key(&executable_stem) {
+            warn!("no rule for subcommand: {}", command.join(" "));
+            Ok(None)
         } else {
             warn!("no rule for executable: {}", executable_stem);
             Ok(None)
@@ -60,7 +98,9 @@ impl Rules {
             "c++ -MF <out> -o <out> <in>...",
             "cc  -MF <out> -o <out> <in>...",
             "sox <in>... <out>",
-            // TODO cmake -E copy <in> <out>
+            "cmake -E copy <in>... <out>",
+            "git -C <> archive -o <out>",
+            "tar -cf <out> <in>...",
         ]
         .iter()
         .for_each(|x| self.add(x).unwrap());
@@ -69,7 +109,11 @@ impl Rules {
 
 struct Rule {
     executable: String,
+    /// fixed words which need to be given in this order, e.g. `["-E", "copy"]` for `cmake -E copy`
+    subcommand: Vec<String>,
     options: HashMap<String, Arg>,
+    /// options with the file attached, e.g. `-o<out>` or `--output=<out>`, by prefix
+    joined_options: Vec<(String, Arg)>,
     positional_args: Vec<Arg>,
 }
 
@@ -79,62 +123,93 @@ impl Rule {
         let executable = items.next().context("Rule is incomplete")?.into();
         let mut named_args: HashMap<String, Arg> = Default::default();
         let mut positional_args: Vec<Arg> = Default::default();
+        let mut subcommand: Vec<String> = Default::default();
         let mut name: Option<&str> = None;
+        let mut joined_options: Vec<(String, Arg)> = Default::default();
         for item in items {
-            let arg = Self::parse_arg(item)?;
-            match (name, arg) {
-                (None, None) => name = Some(item),
-                (Some(n), Some(a)) => {
+            match (name.take(), Self::parse_arg(item)?) {
+                (n, Some((prefix, a))) if !prefix.is_empty() => {
+                    subcommand.extend(n.map(String::from));
+                    joined_options.push((prefix.into(), a));
+                }
+                (Some(n), Some((_, a))) => {
                     named_args.insert(n.into(), a);
-                    name = None;
                 }
-                (None, Some(a)) => positional_args.push(a),
-                (Some(n), None) => bail!(format!("named argument without file spec: {n}")),
+                (None, Some((_, a))) => positional_args.push(a),
+                (n, None) => {
+                    subcommand.extend(n.map(String::from));
+                    if item.starts_with('-') {
+                        name = Some(item);
+                    } else {
+                        subcommand.push(item.into());
+                    }
+                }
             }
         }
+        subcommand.extend(name.map(String::from));
         if positional_args.iter().filter(|x| x.multiple).count() > 1 {
             bail!("only one positional argument might take multiple values");
         }
         Ok(Self {
             executable,
+            subcommand,
             options: named_args,
+            joined_options,
             positional_args,
         })
     }
 
-    fn parse_arg(item: &str) -> Result<Option<Arg>, anyhow::Error> {
-        let open = item.chars().into_iter().filter(|x| *x == '<').count();
-        let close = item.chars().into_iter().filter(|x| *x == '>').count();
-        Ok(if open == 0 && close == 0 {
-            None
-        } else if item == "<in>" {
-            Some(Arg {
-                file_type: ArgFileType::Input,
-                multiple: false,
-            })
-        } else if item == "<in>..." {
-            Some(Arg {
-                file_type: ArgFileType::Input,
-                multiple: true,
+    /// Returns the prefix of a joined file spec, e.g. `-o` of `-o<out>`, and the file spec
+    fn parse_arg(item: &str) -> Result<Option<(&str, Arg)>, anyhow::Error> {
+        let specs = [
+            ("<in>...", ArgFileType::Input, true),
+            ("<in>", ArgFileType::Input, false),
+            ("<out>...", ArgFileType::Output, true),
+            ("<out>", ArgFileType::Output, false),
+            ("<>", ArgFileType::NoFile, false),
+        ];
+        let spec = specs
+            .iter()
+            .find_map(|(spec, file_type, multiple)| {
+                item.strip_suffix(spec).map(|prefix| {
+                    let arg = Arg {
+                        file_type: *file_type,
+                        multiple: *multiple,
+                    };
+                    (prefix, arg)
+                })
             })
-        } else if item == "<out>" {
-            Some(Arg {
-                file_type: ArgFileType::Output,
-                multiple: false,
-            })
-        } else if item == "<out>..." {
-            Some(Arg {
-                file_type: ArgFileType::Output,
-                multiple: true,
-            })
-        } else if item == "<>" {
-            Some(Arg {
-                file_type: ArgFileType::NoFile,
-                multiple: false,
-            })
-        } else {
+            .filter(|(prefix, _)| !prefix.contains(['<', '>']));
+        if spec.is_none() && item.contains(['<', '>']) {
             bail!(format!("syntax error: {item}"))
-        })
+        }
+        Ok(spec)
+    }
+
+    /// Returns the joined option with the longest prefix of an argument
+    fn joined_option(&self, item: &str) -> Option<&(String, Arg)> {
+        self.joined_options
+            .iter()
+            .filter(|(prefix, _)| item.len() > prefix.len() && item.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+    }
+
+    /// Returns true if the subcommand words are found in the command line, skipping options
+    fn matches(&self, command: &[String]) -> bool {
+        let mut subcommand = self.subcommand.iter().peekable();
+        let mut items = command.iter().skip(1);
+        while let Some(item) = items.next() {
+            if subcommand.peek().is_none() {
+                break;
+            } else if subcommand.next_if(|x| *x == item).is_some() {
+                continue;
+            } else if self.options.contains_key(item) {
+                items.next();
+            } else if !item.starts_with('-') && self.joined_option(item).is_none() {
+                return false;
+            }
+        }
+        subcommand.peek().is_none()
     }
 
     pub fn parse_command(
@@ -143,12 +218,26 @@ impl Rule {
     ) -> Result<CommandFileArgIndices, anyhow::Error> {
         let mut command_files: CommandFileArgIndices = Default::default();
         let mut prev_option: Option<&Arg> = None;
+        let mut subcommand = self.subcommand.iter().peekable();
         let mut i_positional = 1;
         for (i, item) in command.iter().enumerate().skip(1) {
+            if prev_option.is_none() && subcommand.next_if(|x| *x == item).is_some() {
+                i_positional = i + 1;
+                continue;
+            }
             let mut is_option = true;
             let curr_option = self.options.get(item);
             match (prev_option, curr_option) {
-                (None, None) => is_option = item.starts_with('-'),
+                (None, None) => {
+                    if let Some((prefix, arg)) = self.joined_option(item) {
+                        command_files.push(arg.file_type, item[prefix.len()..].into());
+                        if arg.file_type != ArgFileType::NoFile {
+                            command_files.joined_prefixes.push(prefix.clone());
+                        }
+                    } else {
+                        is_option = item.starts_with('-');
+                    }
+                }
                 (None, Some(arg)) => prev_option = Some(arg),
                 (Some(arg), None) => {
                     command_files.push(arg.file_type, item.clone());
@@ -199,6 +288,8 @@ enum ArgFileType {
 pub struct CommandFileArgIndices {
     pub inputs: Vec<String>,
     pub outputs: Vec<String>,
+    /// prefixes of arguments with attached files, e.g. `-o` of `-oa.o`
+    pub joined_prefixes: Vec<String>,
 }
 
 impl CommandFileArgIndices {
@@ -210,3 +301,96 @@ impl CommandFileArgIndices {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(x: &str) -> Vec<String> {
+        x.split(' ').map(String::from).collect()
+    }
+
+    #[test]
+    fn add_lines() {
+        let mut rules = Rules::new();
+        rules
+            .add_lines(
+                "# comment\n\nhead -n <> <in>\ncp <out> <in>\n",
+                "razel.rules",
+            )
+            .unwrap();
+        let files = rules
+            .parse_command(&command("head -n 2 a.txt"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(files.inputs, ["a.txt"]);
+        assert!(files.outputs.is_empty());
+        // user rules override the defaults
+        let files = rules.parse_command(&command("cp a b")).unwrap().unwrap();
+        assert_eq!(files.inputs, ["b"]);
+        assert_eq!(files.outputs, ["a"]);
+    }
+
+    #[test]
+    fn subcommands() {
+        let rules = Rules::new();
+        let parse = |x: &str| rules.parse_command(&command(x)).unwrap();
+        let files = parse("cmake -E copy a b c").unwrap();
+        assert_eq!(files.inputs, ["a", "b"]);
+        assert_eq!(files.outputs, ["c"]);
+        assert!(parse("cmake --build build").is_none());
+        assert!(parse("cmake -E remove a").is_none());
+        let files = parse("git -C repo archive -o out.tar HEAD").unwrap();
+        assert!(files.inputs.is_empty());
+        assert_eq!(files.outputs, ["out.tar"]);
+        assert!(parse("git -C repo status").is_none());
+        let files = parse("tar -cf out.tar a b").unwrap();
+        assert_eq!(files.inputs, ["a", "b"]);
+        assert_eq!(files.outputs, ["out.tar"]);
+    }
+
+    #[test]
+    fn longest_subcommand() {
+        let mut rules = Rules::new();
+        rules.add("tool <in>").unwrap();
+        rules.add("tool run <in> <out>").unwrap();
+        rules.add("tool run fast <out>").unwrap();
+        let parse = |x: &str| rules.parse_command(&command(x)).unwrap().unwrap();
+        assert_eq!(parse("tool a").inputs, ["a"]);
+        assert_eq!(parse("tool -v run a b").outputs, ["b"]);
+        let files = parse("tool run fast b");
+        assert!(files.inputs.is_empty());
+        assert_eq!(files.outputs, ["b"]);
+    }
+
+    #[test]
+    fn joined_options() {
+        let mut rules = Rules::new();
+        rules
+            .add("ld -o <out> -o<out> --output=<out> -Wl,-Map=<out> -L<> <in>...")
+            .unwrap();
+        let files = rules
+            .parse_command(&command("ld -oa.out -Wl,-Map=a.map -Llib -v a.o b.o"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(files.inputs, ["a.o", "b.o"]);
+        assert_eq!(files.outputs, ["a.out", "a.map"]);
+        assert_eq!(files.joined_prefixes, ["-o", "-Wl,-Map="]);
+        let files = rules
+            .parse_command(&command("ld --output=a.out -o b.out a.o"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(files.inputs, ["a.o"]);
+        assert_eq!(files.outputs, ["a.out", "b.out"]);
+        assert!(Rules::new().add("ld -o<out>x").is_err());
+    }
+
+    #[test]
+    fn add_lines_error() {
+        let error = Rules::new()
+            .add_lines("cp <in> <out>\n\nsort <input>\n", "razel.rules")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "razel.rules:3: sort <input>");
+        assert_eq!(error.root_cause().to_string(), "syntax error: <input>");
+    }
+}
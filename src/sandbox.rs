@@ -1,51 +1,149 @@
 use std::path::PathBuf;
 use std::process;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use clap::ArgEnum;
+use directories::BaseDirs;
+use itertools::Itertools;
+use serde::Deserialize;
 use tokio::fs;
 
-use crate::{config, force_symlink};
+use crate::{config, force_copy, force_hardlink, force_symlink};
+
+/// Options to isolate custom commands
+#[derive(Clone, Debug)]
+pub struct SandboxConfig {
+    /// Root of all sandbox dirs, should be outside the workspace
+    pub dir: PathBuf,
+    /// Use Linux user/mount namespaces: inputs are mounted read-only, /tmp is private and the
+    /// workspace is hidden. Without this option the sandbox does not stop writing to input files.
+    pub hermetic: bool,
+    /// Allow network access, only used in hermetic mode
+    pub network: bool,
+    /// Fail commands which read files outside of system dirs that are not declared as inputs or
+    /// which create files that are not declared as outputs. Opened files are traced using ptrace.
+    pub detect_undeclared: bool,
+    /// How inputs are put into the sandbox, not used in hermetic mode
+    pub strategy: SandboxStrategy,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            dir: Self::default_dir(),
+            hermetic: false,
+            network: true,
+            detect_undeclared: false,
+            strategy: SandboxStrategy::Symlink,
+        }
+    }
+}
+
+impl SandboxConfig {
+    /// Per-user runtime dir which is usually a tmpfs, with fallback to the tmp dir
+    pub fn default_dir() -> PathBuf {
+        if let Some(runtime_dir) = BaseDirs::new().and_then(|x| x.runtime_dir().map(PathBuf::from))
+        {
+            return runtime_dir.join(config::SANDBOX_DIR);
+        }
+        match std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+            Ok(user) if !user.is_empty() => {
+                std::env::temp_dir().join(format!("{}-{}", config::SANDBOX_DIR, user))
+            }
+            _ => std::env::temp_dir().join(config::SANDBOX_DIR),
+        }
+    }
+
+    /// Parent dir of all sandboxes of this razel process
+    pub fn process_dir(&self) -> PathBuf {
+        self.dir.join(process::id().to_string())
+    }
+}
+
+/// How inputs are put into the sandbox
+#[derive(ArgEnum, Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxStrategy {
+    /// Symlink to the input: fast, but visible for tools which resolve paths
+    Symlink,
+    /// Hardlink to read-only inputs, i.e. files in the cache, copy if that fails, e.g. across
+    /// devices. Writable inputs, e.g. sources, are copied. Running as root ignores read-only files.
+    Hardlink,
+    /// Reflink if supported by the file system, copy otherwise
+    Copy,
+}
+
+/// Dirs of the system, e.g. with toolchains and libraries, which are not checked for undeclared files
+static SYSTEM_DIRS: [&str; 10] = [
+    "/bin", "/dev", "/etc", "/lib", "/lib32", "/lib64", "/proc", "/sbin", "/sys", "/usr",
+];
+
+/// File opened by a traced command, path is resolved by the kernel
+#[derive(Debug, PartialEq)]
+pub struct FileAccess {
+    pub path: PathBuf,
+    pub write: bool,
+}
 
-/// TODO sandbox does not stop writing to input files
 #[derive(Debug)]
 pub struct Sandbox {
     pub dir: PathBuf,
+    pub config: SandboxConfig,
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
 }
 
 impl Sandbox {
-    pub fn new(command_id: &String) -> Self {
+    pub fn new(
+        command_id: &String,
+        config: SandboxConfig,
+        inputs: Vec<PathBuf>,
+        outputs: Vec<PathBuf>,
+    ) -> Self {
         Self {
-            dir: [
-                config::SANDBOX_DIR,
-                ".sandbox",
-                &process::id().to_string(),
-                command_id,
-            ]
-            .iter()
-            .collect(),
-        }
-    }
-
-    /// Create tmp dir, symlink inputs and create output directories
-    pub async fn create(
-        &self,
-        inputs: &Vec<PathBuf>,
-        outputs: &Vec<PathBuf>,
-    ) -> Result<(), anyhow::Error> {
+            dir: config.process_dir().join(command_id),
+            config,
+            inputs,
+            outputs,
+        }
+    }
+
+    /// Create tmp dir, link inputs and create output directories
+    ///
+    /// In hermetic mode empty files are created as mount points for the inputs instead of links.
+    pub async fn create(&self) -> Result<(), anyhow::Error> {
         fs::create_dir_all(&self.dir)
             .await
             .with_context(|| format!("Failed to create sandbox dir: {:?}", self.dir))?;
-        for input in inputs {
+        for input in &self.inputs {
             if input.is_absolute() {
                 continue;
             }
-            let src = fs::canonicalize(&input)
-                .await
-                .with_context(|| format!("Error in canonicalize({:?})", input))?;
             let dst = self.dir.join(&input);
-            force_symlink(&src, &dst).await?;
+            if self.config.hermetic {
+                fs::create_dir_all(dst.parent().unwrap()).await?;
+                fs::write(&dst, [])
+                    .await
+                    .with_context(|| format!("Failed to create mount point: {:?}", dst))?;
+            } else {
+                let src = fs::canonicalize(&input)
+                    .await
+                    .with_context(|| format!("Error in canonicalize({:?})", input))?;
+                match self.config.strategy {
+                    SandboxStrategy::Symlink => force_symlink(&src, &dst).await?,
+                    SandboxStrategy::Hardlink => {
+                        // writes through a link to a writable file would modify the source
+                        if fs::metadata(&src).await?.permissions().readonly() {
+                            force_hardlink(&src, &dst).await?
+                        } else {
+                            force_copy(&src, &dst).await?
+                        }
+                    }
+                    SandboxStrategy::Copy => force_copy(&src, &dst).await?,
+                }
+            }
         }
-        for output in outputs {
+        for output in &self.outputs {
             let output_abs = self.dir.join(&output);
             let dir = output_abs.parent().unwrap();
             fs::create_dir_all(&dir)
@@ -55,9 +153,84 @@ impl Sandbox {
         Ok(())
     }
 
+    /// Check for files created within the sandbox and accessed outside of it which are not declared.
+    ///
+    /// Accesses to system dirs and the tmp dir are ignored.
+    pub async fn check_undeclared(&self, accesses: &[FileAccess]) -> Result<(), anyhow::Error> {
+        let mut undeclared_inputs = vec![];
+        let mut undeclared_outputs = self.undeclared_files_in_sandbox().await?;
+        let sandbox_dir = fs::canonicalize(&self.dir).await?;
+        // other sandboxes might be within the tmp dir
+        let sandboxes_dir = fs::canonicalize(&self.config.dir).await?;
+        let mut system_dirs = SYSTEM_DIRS.map(PathBuf::from).to_vec();
+        system_dirs.extend(fs::canonicalize(std::env::temp_dir()).await);
+        let mut declared_inputs = Vec::with_capacity(self.inputs.len());
+        for input in &self.inputs {
+            declared_inputs.extend(fs::canonicalize(input).await);
+        }
+        for access in accesses {
+            let path = &access.path;
+            if path.starts_with(&sandbox_dir)
+                || (!path.starts_with(&sandboxes_dir)
+                    && system_dirs.iter().any(|x| path.starts_with(x)))
+                || path.is_dir()
+            {
+                continue;
+            } else if access.write {
+                undeclared_outputs.push(path.clone());
+            } else if !declared_inputs.contains(path) {
+                undeclared_inputs.push(path.clone());
+            }
+        }
+        if undeclared_inputs.is_empty() && undeclared_outputs.is_empty() {
+            return Ok(());
+        }
+        let format = |x: Vec<PathBuf>| x.iter().unique().map(|x| format!("{:?}", x)).join(", ");
+        bail!(
+            "undeclared inputs: [{}], undeclared outputs: [{}]",
+            format(undeclared_inputs),
+            format(undeclared_outputs)
+        )
+    }
+
+    /// Returns files created by the command within the sandbox which are not declared as outputs
+    async fn undeclared_files_in_sandbox(&self) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let mut undeclared = vec![];
+        let mut dirs = vec![self.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let rel_path = path.strip_prefix(&self.dir)?;
+                if !self.inputs.iter().any(|x| x == rel_path)
+                    && !self.outputs.iter().any(|x| x == rel_path)
+                {
+                    undeclared.push(rel_path.into());
+                }
+            }
+        }
+        Ok(undeclared)
+    }
+
     /// Remove tmp dir
     pub async fn destroy(&self) -> Result<(), anyhow::Error> {
         fs::remove_dir_all(&self.dir).await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox_dir_outside_workspace() {
+        let sandbox = Sandbox::new(&"0".into(), Default::default(), vec![], vec![]);
+        assert!(sandbox.dir.is_absolute());
+        assert!(!sandbox.dir.starts_with(std::env::current_dir().unwrap()));
+    }
+}
@@ -1,21 +1,25 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use std::{env, fs};
 
 use anyhow::{bail, Context};
 use itertools::Itertools;
-use log::{debug, error, info, warn};
+use log::{debug, info, warn};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 use which::which;
 
 use crate::bazel_remote_exec::command::EnvironmentVariable;
 use crate::bazel_remote_exec::{ActionResult, Digest, OutputFile};
-use crate::cache::{BlobDigest, Cache, LocalCache, MessageDigest};
+use crate::cache::{
+    BlobDigest, Cache, DigestFunction, LocalCache, MessageDigest, StatCache, StatCacheEntry,
+};
 use crate::executors::{ExecutionResult, ExecutionStatus, Executor};
 use crate::{
-    bazel_remote_exec, config, Arena, Command, CommandBuilder, CommandId, File, FileId, Sandbox,
+    bazel_remote_exec, canonicalize_path, config, parse_depfile, total_memory, Arena, Blocked,
+    Command, CommandBuilder, CommandId, Event, EventWriter, File, FileId, History, HistoryEntry,
+    Profile, ProfileThread, Progress, Resources, Sandbox, SandboxConfig, SCHEDULER_TID,
 };
 
 #[derive(Debug, PartialEq)]
@@ -48,8 +52,14 @@ pub struct SchedulerExecStats {
 
 type ExecutionResultChannel = (CommandId, ExecutionResult, Option<ActionResult>);
 
+type DigestInputFileChannel = (
+    FileId,
+    Result<(BlobDigest, Option<StatCacheEntry>), anyhow::Error>,
+);
+
 pub struct Scheduler {
     pub read_cache: bool,
+    pub sandbox_config: SandboxConfig,
     worker_threads: usize,
     /// absolute directory to resolve relative paths of input/output files
     workspace_dir: PathBuf,
@@ -68,9 +78,18 @@ pub struct Scheduler {
     // TODO sort by weight, e.g. recursive number of rdeps
     ready: VecDeque<CommandId>,
     running: usize,
+    /// CPU slots and memory used by running commands
+    resources: Resources,
     succeeded: Vec<CommandId>,
     failed: Vec<CommandId>,
     cache_hits: usize,
+    /// measured executions, loaded in run()
+    history: History,
+    progress: Progress,
+    profile: Profile,
+    /// file to write the profile to, profiling is disabled if None
+    profile_path: Option<PathBuf>,
+    events: Option<EventWriter>,
 }
 
 impl Scheduler {
@@ -84,11 +103,12 @@ impl Scheduler {
         debug!("out_dir:       {:?}", out_dir);
         Scheduler {
             read_cache: true,
+            sandbox_config: Default::default(),
             worker_threads,
             workspace_dir,
             current_dir,
             out_dir,
-            cache: Cache::new().unwrap(),
+            cache: Cache::new(Default::default()).unwrap(),
             files: Default::default(),
             path_to_file_id: Default::default(),
             which_to_file_id: Default::default(),
@@ -97,9 +117,15 @@ impl Scheduler {
             waiting: Default::default(),
             ready: Default::default(),
             running: 0,
+            resources: Resources::new(worker_threads, total_memory()),
             succeeded: vec![],
             failed: vec![],
             cache_hits: 0,
+            history: Default::default(),
+            progress: Progress::new(worker_threads),
+            profile: Default::default(),
+            profile_path: None,
+            events: None,
         }
     }
 
@@ -118,14 +144,66 @@ impl Scheduler {
         debug!("workspace_dir: {:?}", self.workspace_dir);
     }
 
+    /// Select the hash function for digests, the local cache is separated per function
+    pub fn set_digest_function(
+        &mut self,
+        digest_function: DigestFunction,
+    ) -> Result<(), anyhow::Error> {
+        if digest_function != self.cache.digest_function {
+            self.cache = Cache::new(digest_function)?;
+        }
+        Ok(())
+    }
+
+    /// Record a Chrome trace-event profile of the run and write it to a file
+    pub fn enable_profile(&mut self, path: PathBuf) {
+        self.profile = Profile::new();
+        self.profile_path = Some(path);
+    }
+
+    /// Write events to a jsonl file while running
+    pub fn enable_events(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        self.events = Some(EventWriter::new(path)?);
+        Ok(())
+    }
+
+    fn emit(&mut self, event: Event) {
+        if let Some(events) = &mut self.events {
+            if let Err(e) = events.write(event) {
+                warn!("failed to write event, disabling event stream: {:?}", e);
+                self.events = None;
+            }
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.commands.len()
     }
 
     pub fn show_info(&self) {
-        println!("output directory: {:?}", self.out_dir);
-        println!("cache directory:  {:?}", LocalCache::dir());
-        println!("worker threads:   {}", self.worker_threads);
+        println!("output directory:  {:?}", self.out_dir);
+        println!("cache directory:   {:?}", LocalCache::dir());
+        println!("digest function:   {}", self.cache.digest_function.name());
+        println!("sandbox directory: {:?}", self.sandbox_config.dir);
+        println!("worker threads:    {}", self.worker_threads);
+        if let Some(memory) = self.resources.memory() {
+            println!("memory:            {} MiB", memory);
+        }
+    }
+
+    /// Declare a pool to limit concurrently running commands, see ResourceRequest::pool
+    pub fn add_pool(
+        &mut self,
+        name: String,
+        depth: usize,
+        executables: Vec<String>,
+    ) -> Result<(), anyhow::Error> {
+        self.resources.add_pool(name, depth, executables)
+    }
+
+    /// Declare a lane or change its limit, see ResourceRequest::lane
+    pub fn set_lane(&mut self, name: String, limit: usize) -> Result<(), anyhow::Error> {
+        self.resources.set_lane(name, limit)
     }
 
     pub fn push_custom_command(
@@ -158,6 +236,11 @@ impl Scheduler {
             assert!(output.creating_command.is_none());
             output.creating_command = Some(id);
         }
+        let command = &self.commands[id];
+        self.emit(Event::CommandAdded {
+            command: command.name.clone(),
+            command_line: command.executor.command_line(),
+        });
         Ok(id)
     }
 
@@ -178,20 +261,48 @@ impl Scheduler {
         if self.commands.is_empty() {
             bail!("no commands added");
         }
+        self.check_resources()?;
+        let span_start = Instant::now();
         self.create_dependency_graph();
+        self.profile.span(
+            "create dependency graph",
+            "scheduler",
+            SCHEDULER_TID,
+            span_start,
+        );
+        let span_start = Instant::now();
         self.digest_input_files().await?;
+        self.profile
+            .span("digest input files", "scheduler", SCHEDULER_TID, span_start);
         self.create_output_dirs()?;
+        self.history = History::load(&History::default_path());
+        for command in self.commands.iter() {
+            self.progress
+                .add(command.id, self.history.estimated_duration(&command.name));
+        }
         let (tx, mut rx) = mpsc::channel(32);
+        let mut progress_interval = tokio::time::interval(Progress::INTERVAL);
         let execution_start = Instant::now();
         self.start_ready_commands(&tx);
         while self.ready.len() + self.running != 0 {
-            if let Some((id, execution_result, action_result)) = rx.recv().await {
-                self.on_command_finished(id, execution_result, action_result)
-                    .await;
-                self.start_ready_commands(&tx);
+            tokio::select! {
+                Some((id, execution_result, action_result)) = rx.recv() => {
+                    self.on_command_finished(id, execution_result, action_result)
+                        .await;
+                    self.start_ready_commands(&tx);
+                }
+                _ = progress_interval.tick() => self.progress.tick(),
             }
         }
-        Ok(SchedulerStats {
+        self.progress.finish();
+        fs::remove_dir(self.sandbox_config.process_dir()).ok();
+        if let Err(e) = self.history.save() {
+            warn!("failed to save history: {:?}", e);
+        }
+        if let Some(path) = &self.profile_path {
+            self.profile.write(path)?;
+        }
+        let stats = SchedulerStats {
             exec: SchedulerExecStats {
                 succeeded: self.succeeded.len(),
                 failed: self.failed.len(),
@@ -200,7 +311,33 @@ impl Scheduler {
             cache_hits: self.cache_hits,
             preparation_duration: execution_start.duration_since(preparation_start),
             execution_duration: execution_start.elapsed(),
-        })
+        };
+        self.emit_run_finished(&stats);
+        Ok(stats)
+    }
+
+    fn emit_run_finished(&mut self, stats: &SchedulerStats) {
+        if self.events.is_none() {
+            return;
+        }
+        let skipped = self
+            .waiting
+            .iter()
+            .chain(self.ready.iter())
+            .map(|x| self.commands[*x].name.clone())
+            .sorted_unstable()
+            .collect::<Vec<_>>();
+        for command in skipped {
+            self.emit(Event::CommandSkipped { command });
+        }
+        self.emit(Event::Summary {
+            succeeded: stats.exec.succeeded,
+            failed: stats.exec.failed,
+            not_run: stats.exec.not_run,
+            cache_hits: stats.cache_hits,
+            preparation_duration: stats.preparation_duration.as_secs_f64(),
+            execution_duration: stats.execution_duration.as_secs_f64(),
+        });
     }
 
     /// Register an executable to be used for a command
@@ -267,16 +404,21 @@ impl Scheduler {
         Ok(&self.files[id])
     }
 
+    /// Path of a not yet declared output file, as used in command lines
+    pub fn out_path(&self, arg: &String) -> Result<PathBuf, anyhow::Error> {
+        Ok(self.out_dir.join(self.rel_path(arg)?))
+    }
+
     /// Maps a relative path from workspace dir to cwd, allow absolute path
-    fn rel_path(&self, arg: &String) -> Result<PathBuf, anyhow::Error> {
+    pub fn rel_path(&self, arg: &String) -> Result<PathBuf, anyhow::Error> {
         let path = Path::new(arg);
         if path.is_absolute() {
             Ok(PathBuf::from(
                 path.strip_prefix(&self.current_dir).unwrap_or(path),
             ))
         } else {
-            self.workspace_dir
-                .join(path)
+            let path = self.workspace_dir.join(path);
+            PathBuf::from(canonicalize_path(path.to_str().unwrap()))
                 .strip_prefix(&self.current_dir)
                 .map(PathBuf::from)
                 .with_context(|| {
@@ -288,6 +430,25 @@ impl Scheduler {
         }
     }
 
+    /// Check pools/lanes requested by name and assign pools by executable
+    fn check_resources(&mut self) -> Result<(), anyhow::Error> {
+        for command in self.commands.iter_mut() {
+            if let Some(lane) = &command.resources.lane {
+                if !self.resources.has_lane(lane) {
+                    bail!("lane {lane:?} of command {} is not declared", command.name);
+                }
+            }
+            if let Some(pool) = &command.resources.pool {
+                if !self.resources.has_pool(pool) {
+                    bail!("pool {pool:?} of command {} is not declared", command.name);
+                }
+            } else if let Executor::CustomCommand(x) = &command.executor {
+                command.resources.pool = self.resources.pool_for_executable(&x.executable).cloned();
+            }
+        }
+        Ok(())
+    }
+
     fn create_dependency_graph(&mut self) {
         self.waiting.reserve(self.commands.len());
         self.succeeded.reserve(self.commands.len());
@@ -311,6 +472,11 @@ impl Scheduler {
         for (id, rdep) in rdeps {
             self.commands[id].reverse_deps.push(rdep);
         }
+        for id in self.ready.clone() {
+            self.emit(Event::CommandReady {
+                command: self.commands[id].name.clone(),
+            });
+        }
         self.check_for_circular_dependencies();
         assert!(!self.ready.is_empty());
     }
@@ -319,18 +485,23 @@ impl Scheduler {
         // TODO
     }
 
+    /// Digest all input files, reusing digests of unchanged files from the stat cache
     async fn digest_input_files(&mut self) -> Result<(), anyhow::Error> {
+        let mut stat_cache = StatCache::load(&StatCache::default_path(self.cache.digest_function));
         let concurrent = self.worker_threads;
         let (tx, mut rx) = mpsc::channel(concurrent);
         let mut tx_option = Some(tx);
         let mut next_file_id = self.files.first_id();
         for _ in 0..concurrent {
-            self.spawn_digest_input_file(&mut next_file_id, &mut tx_option);
+            self.spawn_digest_input_file(&mut stat_cache, &mut next_file_id, &mut tx_option);
         }
         let mut missing_files = 0;
         while let Some((id, result)) = rx.recv().await {
             match result {
-                Ok(digest) => {
+                Ok((digest, entry)) => {
+                    if let Some(entry) = entry {
+                        stat_cache.insert(self.current_dir.join(&self.files[id].exec_path), entry);
+                    }
                     self.files[id].digest = Some(digest);
                 }
                 Err(x) => {
@@ -338,7 +509,10 @@ impl Scheduler {
                     missing_files += 1;
                 }
             };
-            self.spawn_digest_input_file(&mut next_file_id, &mut tx_option);
+            self.spawn_digest_input_file(&mut stat_cache, &mut next_file_id, &mut tx_option);
+        }
+        if let Err(e) = stat_cache.save() {
+            warn!("failed to save stat cache: {:?}", e);
         }
         if missing_files != 0 {
             bail!("{missing_files} input files not found!");
@@ -348,8 +522,9 @@ impl Scheduler {
 
     fn spawn_digest_input_file(
         &self,
+        stat_cache: &mut StatCache,
         next_id: &mut FileId,
-        tx_option: &mut Option<Sender<(FileId, Result<BlobDigest, anyhow::Error>)>>,
+        tx_option: &mut Option<Sender<DigestInputFileChannel>>,
     ) {
         if tx_option.is_none() {
             return;
@@ -357,10 +532,17 @@ impl Scheduler {
         while let Some(file) = self.files.get_and_inc_id(next_id) {
             if file.creating_command.is_none() {
                 let id = file.id;
-                let path = file.exec_path.clone();
+                let path = self.current_dir.join(&file.exec_path);
+                let cached = stat_cache.get(&path).cloned();
+                let digest_function = self.cache.digest_function;
                 let tx = tx_option.clone().unwrap();
                 tokio::spawn(async move {
-                    tx.send((id, Digest::for_file(path).await)).await.ok();
+                    tx.send((
+                        id,
+                        StatCache::digest_file(path, cached, digest_function).await,
+                    ))
+                    .await
+                    .ok();
                 });
                 return;
             }
@@ -382,10 +564,20 @@ impl Scheduler {
         Ok(())
     }
 
+    /// Start ready commands in order as long as their requested resources are available.
+    ///
+    /// Commands waiting for a pool, lane or lock are skipped to not block unrelated commands.
     fn start_ready_commands(&mut self, tx: &Sender<ExecutionResultChannel>) {
-        while self.running < self.worker_threads && !self.ready.is_empty() {
-            let id = self.ready.pop_front().unwrap();
-            self.start_next_command(id, tx.clone());
+        let mut i = 0;
+        while let Some(id) = self.ready.get(i).cloned() {
+            match self.resources.try_acquire(&self.commands[id].resources) {
+                Ok(()) => {
+                    self.ready.remove(i);
+                    self.start_next_command(id, tx.clone());
+                }
+                Err(Blocked::Named) => i += 1,
+                Err(Blocked::Machine) => break,
+            }
         }
     }
 
@@ -411,49 +603,84 @@ impl Scheduler {
     /// Panic only in case of system errors.
     fn start_next_command(&mut self, id: CommandId, tx: Sender<ExecutionResultChannel>) {
         self.running += 1;
+        let name = self.commands[id].name.clone();
+        self.emit(Event::CommandStarted {
+            command: name.clone(),
+        });
         let command = &self.commands[id];
         assert_eq!(command.schedule_state, ScheduleState::Ready);
         assert_eq!(command.unfinished_deps.len(), 0);
         let action = self.get_bzl_action_for_command(command);
-        let action_digest = Digest::for_message(&action);
-        info!("Execute {}", command.name);
+        let action_digest = Digest::for_message(&action, self.cache.digest_function);
+        self.progress.on_start(id, &name);
         let cache = self.cache.clone();
-        let read_cache = self.read_cache;
+        let do_not_cache = action.do_not_cache;
+        let read_cache = self.read_cache && !do_not_cache;
         let executor = command.executor.clone();
         let input_paths = self.collect_input_file_paths_for_command(command);
         let output_paths = self.collect_output_file_paths_for_command(command);
-        let sandbox = executor
-            .use_sandbox()
-            .then(|| Sandbox::new(&command.id.to_string()));
+        let sandbox = (executor.use_sandbox() && !command.undeclared_inputs).then(|| {
+            let mut config = self.sandbox_config.clone();
+            if let Some(strategy) = command.sandbox_strategy {
+                config.strategy = strategy;
+            }
+            Sandbox::new(
+                &command.id.to_string(),
+                config,
+                input_paths,
+                output_paths.clone(),
+            )
+        });
         let out_dir = self.out_dir.clone();
+        let profile = self.profile.clone();
         tokio::task::spawn(async move {
-            let (execution_result, action_result) = if let Some(x) =
-                Self::get_action_from_cache(&action_digest, &cache, read_cache).await
-            {
+            let thread = profile.thread();
+            let command_start = Instant::now();
+            let span_start = Instant::now();
+            let cached = Self::get_action_from_cache(&action_digest, &cache, read_cache).await;
+            thread.span("cache lookup", "cache", span_start);
+            let (execution_result, action_result) = if let Some(x) = cached {
                 x
             } else {
-                Self::exec_action(
-                    &action_digest,
+                let (execution_result, action_result) = Self::exec_action(
                     &cache,
                     &executor,
-                    &input_paths,
                     &output_paths,
                     &sandbox,
                     &out_dir,
+                    &thread,
                 )
                 .await
                 .context("exec_action()")
                 .with_context(|| executor.command_line())
-                .unwrap()
+                .unwrap();
+                if let Some(action_result) = action_result.as_ref().filter(|_| !do_not_cache) {
+                    cache
+                        .push_action_result(&action_digest, action_result)
+                        .await;
+                }
+                (execution_result, action_result)
             };
             if let Some(action_result) = &action_result {
+                let span_start = Instant::now();
                 cache
                     .symlink_output_files_into_out_dir(action_result, &out_dir)
                     .await
                     .context("symlink_output_files_into_out_dir()")
                     .with_context(|| executor.command_line())
                     .unwrap();
+                thread.span("symlink outputs", "cache", span_start);
             }
+            thread.span_with_args(
+                &name,
+                "command",
+                command_start,
+                Some(serde_json::json!({
+                    "status": format!("{:?}", execution_result.status),
+                    "cache_hit": execution_result.cache_hit,
+                })),
+            );
+            drop(thread);
             tx.send((id, execution_result, action_result))
                 .await
                 .unwrap();
@@ -473,6 +700,7 @@ impl Scheduler {
                     exit_code,
                     error: None,
                     cache_hit: true,
+                    ..Default::default()
                 };
                 return Some((execution_result, Some(action_result)));
             }
@@ -481,19 +709,17 @@ impl Scheduler {
     }
 
     async fn exec_action(
-        action_digest: &MessageDigest,
         cache: &Cache,
         executor: &Executor,
-        input_paths: &Vec<PathBuf>,
         output_paths: &Vec<PathBuf>,
         sandbox: &Option<Sandbox>,
         out_dir: &PathBuf,
+        thread: &ProfileThread,
     ) -> Result<(ExecutionResult, Option<ActionResult>), anyhow::Error> {
         if let Some(sandbox) = &sandbox {
-            sandbox
-                .create(&input_paths, &output_paths)
-                .await
-                .context("Sandbox::create()")?;
+            let span_start = Instant::now();
+            sandbox.create().await.context("Sandbox::create()")?;
+            thread.span("create sandbox", "sandbox", span_start);
         } else {
             // remove expected output files for tasks, because symlinks will not be overwritten
             // maybe a proper sandbox would be better
@@ -501,11 +727,14 @@ impl Scheduler {
                 fs::remove_file(x).ok();
             }
         }
-        let execution_result = executor.exec(sandbox.as_ref().map(|x| x.dir.clone())).await;
+        let start = Instant::now();
+        let mut execution_result = executor.exec(sandbox.as_ref()).await;
+        execution_result.duration = Some(start.elapsed());
+        thread.span("execute", "execute", start);
+        let span_start = Instant::now();
         let action_result = if execution_result.success() {
             Some(
                 Self::cache_action_result(
-                    &action_digest,
                     &execution_result,
                     &output_paths,
                     sandbox.as_ref().map(|x| x.dir.clone()),
@@ -518,17 +747,22 @@ impl Scheduler {
         } else {
             None
         };
+        if action_result.is_some() {
+            thread.span("cache outputs", "cache", span_start);
+        }
         if let Some(sandbox) = &sandbox {
+            let span_start = Instant::now();
             sandbox
                 .destroy()
                 .await
                 .with_context(|| "Sandbox::destroy()")?;
+            thread.span("destroy sandbox", "sandbox", span_start);
         }
         Ok((execution_result, action_result))
     }
 
+    /// Move the output files into the cache, the action result is pushed by the caller
     async fn cache_action_result(
-        action_digest: &MessageDigest,
         execution_result: &ExecutionResult,
         output_paths: &Vec<PathBuf>,
         sandbox_dir: Option<PathBuf>,
@@ -557,9 +791,6 @@ impl Scheduler {
             stderr_digest: None,
             execution_metadata: None,
         };
-        cache
-            .push_action_result(action_digest, &action_result)
-            .await;
         Ok(action_result)
     }
 
@@ -570,14 +801,59 @@ impl Scheduler {
         action_result: Option<ActionResult>,
     ) {
         self.running -= 1;
+        self.resources.release(&self.commands[id].resources);
+        let name = self.commands[id].name.clone();
+        if execution_result.cache_hit {
+            self.emit(Event::CommandCacheHit {
+                command: name.clone(),
+            });
+        }
+        let output_files = action_result
+            .as_ref()
+            .map_or(&[][..], |x| x.output_files.as_slice());
+        self.emit(Event::command_finished(
+            name,
+            &execution_result,
+            output_files,
+        ));
         if execution_result.success() {
-            self.set_output_file_digests(action_result.unwrap().output_files);
+            let action_result = action_result.unwrap();
+            if !execution_result.cache_hit {
+                self.push_history_entry(id, &execution_result, &action_result);
+            }
+            self.set_output_file_digests(action_result.output_files);
             self.on_command_succeeded(id, execution_result);
+            self.add_inputs_from_deps_files(id).await;
         } else {
             self.on_command_failed(id, execution_result);
         }
     }
 
+    fn push_history_entry(
+        &mut self,
+        id: CommandId,
+        execution_result: &ExecutionResult,
+        action_result: &ActionResult,
+    ) {
+        let command = &self.commands[id];
+        let action_digest = Digest::for_message(
+            &self.get_bzl_action_for_command(command),
+            self.cache.digest_function,
+        )
+        .hash;
+        let output_sizes = action_result
+            .output_files
+            .iter()
+            .map(|x| {
+                let size = x.digest.as_ref().map_or(0, |x| x.size_bytes as u64);
+                (x.path.clone(), size)
+            })
+            .collect::<BTreeMap<_, _>>();
+        if let Some(entry) = HistoryEntry::new(action_digest, execution_result, output_sizes) {
+            self.history.push(command.name.clone(), entry);
+        }
+    }
+
     fn set_output_file_digests(&mut self, output_files: Vec<OutputFile>) {
         for output_file in output_files {
             let mut output_file_path = PathBuf::from(output_file.path);
@@ -599,7 +875,8 @@ impl Scheduler {
         }
         let command = &mut self.commands[id];
         command.schedule_state = ScheduleState::Succeeded;
-        info!("Success {}: {:?}", command.name, execution_result);
+        self.progress
+            .on_finished(id, &command.name, &execution_result);
         for rdep_id in command.reverse_deps.clone() {
             let rdep = &mut self.commands[rdep_id];
             assert_eq!(rdep.schedule_state, ScheduleState::Waiting);
@@ -608,16 +885,75 @@ impl Scheduler {
                 .swap_remove(rdep.unfinished_deps.iter().position(|x| *x == id).unwrap());
             if rdep.unfinished_deps.is_empty() {
                 rdep.schedule_state = ScheduleState::Ready;
+                let command = rdep.name.clone();
                 self.waiting.remove(&rdep_id);
                 self.ready.push_back(rdep_id);
+                self.emit(Event::CommandReady { command });
+            }
+        }
+    }
+
+    /// Add the inputs listed in the deps files of reverse dependencies which became ready
+    async fn add_inputs_from_deps_files(&mut self, id: CommandId) {
+        for rdep in self.commands[id].reverse_deps.clone() {
+            let command = &mut self.commands[rdep];
+            if command.schedule_state != ScheduleState::Ready {
+                continue;
+            }
+            let Some(deps_file) = command.deps_file.take() else {
+                continue;
+            };
+            if let Err(e) = self.add_inputs_from_deps_file(rdep, deps_file).await {
+                self.ready.retain(|x| *x != rdep);
+                let result = ExecutionResult {
+                    status: ExecutionStatus::FailedToStart,
+                    error: Some(e.context("Failed to add inputs from deps file")),
+                    ..Default::default()
+                };
+                self.emit(Event::command_finished(
+                    self.commands[rdep].name.clone(),
+                    &result,
+                    &[],
+                ));
+                self.on_command_failed(rdep, result);
+            }
+        }
+    }
+
+    async fn add_inputs_from_deps_file(
+        &mut self,
+        id: CommandId,
+        deps_file: FileId,
+    ) -> Result<(), anyhow::Error> {
+        let path = &self.files[deps_file].out_path;
+        let text = fs::read_to_string(path).with_context(|| format!("{path:?}"))?;
+        let dir = match self.commands[id].executor.working_dir() {
+            Some(x) => self.current_dir.join(x),
+            None => self.current_dir.clone(),
+        };
+        for dep in parse_depfile(&text) {
+            let file = self.input_file(canonicalize_path(dir.join(&dep).to_str().unwrap()))?;
+            let file_id = file.id;
+            if file.digest.is_none() {
+                if file.creating_command.is_some() {
+                    bail!("generated file is not declared as input: {dep}");
+                }
+                let path = self.current_dir.join(&self.files[file_id].exec_path);
+                self.files[file_id].digest =
+                    Some(Digest::for_file(path, self.cache.digest_function).await?);
+            }
+            let command = &mut self.commands[id];
+            if !command.inputs.contains(&file_id) {
+                command.inputs.push(file_id);
             }
         }
+        Ok(())
     }
 
     fn on_command_failed(&mut self, id: CommandId, result: ExecutionResult) {
         self.failed.push(id);
         let command = &self.commands[id];
-        error!("Error  {}: {:?}", command.name, result);
+        self.progress.on_finished(id, &command.name, &result);
     }
 
     fn get_bzl_action_for_command(&self, command: &Command) -> bazel_remote_exec::Action {
@@ -642,7 +978,11 @@ impl Scheduler {
                 .dedup()
                 .map_into()
                 .collect(),
-            working_directory: "".to_string(),
+            working_directory: command
+                .executor
+                .working_dir()
+                .map(|x| x.to_str().unwrap().into())
+                .unwrap_or_default(),
             ..Default::default()
         };
         // TODO properly build bazel_remote_exec::Directory tree
@@ -671,8 +1011,28 @@ impl Scheduler {
             node_properties: None,
         };
         let bzl_action = bazel_remote_exec::Action {
-            command_digest: Some(Digest::for_message(&bzl_command)),
-            input_root_digest: Some(Digest::for_message(&bzl_input_root)),
+            command_digest: Some(Digest::for_message(
+                &bzl_command,
+                self.cache.digest_function,
+            )),
+            input_root_digest: Some(Digest::for_message(
+                &bzl_input_root,
+                self.cache.digest_function,
+            )),
+            timeout: command.executor.timeout().map(|x| prost_types::Duration {
+                seconds: x.as_secs() as i64,
+                nanos: x.subsec_nanos() as i32,
+            }),
+            platform: Some(command.executor.platform_properties())
+                .filter(|x| !x.is_empty())
+                .map(|x| bazel_remote_exec::Platform {
+                    properties: x
+                        .into_iter()
+                        .sorted_unstable()
+                        .map(|(name, value)| bazel_remote_exec::platform::Property { name, value })
+                        .collect(),
+                }),
+            do_not_cache: command.undeclared_inputs,
             ..Default::default()
         };
         bzl_action
@@ -681,10 +1041,91 @@ impl Scheduler {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
     use approx::assert_abs_diff_eq;
     use serial_test::serial;
 
-    use crate::{Scheduler, SchedulerExecStats};
+    use crate::{
+        CommandBuilder, ResourceRequest, SandboxStrategy, Scheduler, SchedulerExecStats,
+        NETWORK_LANE,
+    };
+
+    /// Returns a command which sleeps and then optionally creates an output file
+    fn sleep_command(
+        scheduler: &mut Scheduler,
+        name: &str,
+        sleep_duration: f64,
+        input: Option<&str>,
+        output: Option<&str>,
+        resources: ResourceRequest,
+    ) -> CommandBuilder {
+        let script = if output.is_some() {
+            "sleep $0 && touch $1"
+        } else {
+            "sleep $0"
+        };
+        let mut args = vec!["-c".into(), script.into(), sleep_duration.to_string()];
+        args.extend(output.map(|x| x.to_string()));
+        let mut builder = CommandBuilder::new(name.into(), args);
+        if let Some(x) = input {
+            builder.input(&x.into(), scheduler).unwrap();
+        }
+        if let Some(x) = output {
+            builder.output(&x.into(), scheduler).unwrap();
+        }
+        builder
+            .custom_command_executor("sh".into(), Default::default(), scheduler)
+            .unwrap();
+        builder.resources(resources);
+        builder
+    }
+
+    /// Push two commands blocked by each other followed by a chain of two free commands.
+    ///
+    /// The chain finishes in parallel to the blocked commands only if those do not block the ready queue.
+    /// The resources of both must fit on a test machine with a single CPU.
+    async fn assert_blocked_commands_are_skipped(
+        mut scheduler: Scheduler,
+        blocked: ResourceRequest,
+        free: ResourceRequest,
+    ) {
+        scheduler.read_cache = false;
+        let sleep_duration = 0.5;
+        for i in 0..2 {
+            let builder = sleep_command(
+                &mut scheduler,
+                &format!("blocked-{i}"),
+                sleep_duration,
+                None,
+                None,
+                blocked.clone(),
+            );
+            scheduler.push(builder).unwrap();
+        }
+        for (name, input, output) in [
+            ("free", None, Some("free.txt")),
+            ("dependent", Some("free.txt"), None),
+        ] {
+            let builder = sleep_command(
+                &mut scheduler,
+                name,
+                sleep_duration,
+                input,
+                output,
+                free.clone(),
+            );
+            scheduler.push(builder).unwrap();
+        }
+        let stats = scheduler.run().await.unwrap();
+        assert_eq!(stats.exec.succeeded, 4);
+        assert_abs_diff_eq!(
+            stats.execution_duration.as_secs_f64(),
+            2.0 * sleep_duration,
+            epsilon = sleep_duration * 0.5
+        );
+    }
 
     /// Test that commands are actually run in parallel limited by Scheduler::worker_threads
     #[tokio::test]
@@ -722,4 +1163,295 @@ mod tests {
             epsilon = sleep_duration * 0.5
         );
     }
+
+    /// Test that exclusive commands are not run in parallel to other commands
+    #[tokio::test]
+    #[serial]
+    async fn exclusive() {
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        let n = 3;
+        let sleep_duration = 0.5;
+        for i in 0..n {
+            let mut builder = CommandBuilder::new(
+                format!("{}", i),
+                vec!["-E".into(), "sleep".into(), sleep_duration.to_string()],
+            );
+            builder
+                .custom_command_executor("cmake".into(), Default::default(), &mut scheduler)
+                .unwrap();
+            builder.resources(ResourceRequest {
+                exclusive: true,
+                ..Default::default()
+            });
+            scheduler.push(builder).unwrap();
+        }
+        let stats = scheduler.run().await.unwrap();
+        assert_eq!(stats.exec.succeeded, n);
+        assert_abs_diff_eq!(
+            stats.execution_duration.as_secs_f64(),
+            n as f64 * sleep_duration,
+            epsilon = sleep_duration * 0.5
+        );
+    }
+
+    /// Test that commands joining a pool by executable are limited by the pool depth
+    #[tokio::test]
+    #[serial]
+    async fn pool() {
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        scheduler
+            .add_pool("cmake".into(), 1, vec!["cmake".into()])
+            .unwrap();
+        let n = 3;
+        let sleep_duration = 0.5;
+        for i in 0..n {
+            scheduler
+                .push_custom_command(
+                    format!("{}", i),
+                    "cmake".into(),
+                    vec!["-E".into(), "sleep".into(), sleep_duration.to_string()],
+                    Default::default(),
+                    vec![],
+                    vec![],
+                )
+                .unwrap();
+        }
+        let stats = scheduler.run().await.unwrap();
+        assert_eq!(stats.exec.succeeded, n);
+        assert_abs_diff_eq!(
+            stats.execution_duration.as_secs_f64(),
+            n as f64 * sleep_duration,
+            epsilon = sleep_duration * 0.5
+        );
+    }
+
+    /// Test that commands in a lane run in addition to commands using all CPU slots
+    #[tokio::test]
+    #[serial]
+    async fn lane() {
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        let threads = scheduler.worker_threads;
+        scheduler.set_lane(NETWORK_LANE.into(), threads).unwrap();
+        let sleep_duration = 0.5;
+        for i in 0..threads * 2 {
+            let mut builder = CommandBuilder::new(
+                format!("{}", i),
+                vec!["-E".into(), "sleep".into(), sleep_duration.to_string()],
+            );
+            builder
+                .custom_command_executor("cmake".into(), Default::default(), &mut scheduler)
+                .unwrap();
+            if i % 2 == 0 {
+                builder.resources(ResourceRequest {
+                    lane: Some(NETWORK_LANE.into()),
+                    ..Default::default()
+                });
+            }
+            scheduler.push(builder).unwrap();
+        }
+        let stats = scheduler.run().await.unwrap();
+        assert_eq!(stats.exec.succeeded, threads * 2);
+        assert_abs_diff_eq!(
+            stats.execution_duration.as_secs_f64(),
+            sleep_duration,
+            epsilon = sleep_duration * 0.5
+        );
+    }
+
+    /// Test that commands waiting for a full pool do not block other commands
+    #[tokio::test]
+    #[serial]
+    async fn pool_does_not_block_other_commands() {
+        let mut scheduler = Scheduler::new();
+        scheduler.add_pool("license".into(), 1, vec![]).unwrap();
+        assert_blocked_commands_are_skipped(
+            scheduler,
+            ResourceRequest {
+                pool: Some("license".into()),
+                ..Default::default()
+            },
+            ResourceRequest {
+                lane: Some(NETWORK_LANE.into()),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    /// Test that commands waiting for a full lane do not block commands using CPU slots
+    #[tokio::test]
+    #[serial]
+    async fn lane_does_not_block_other_commands() {
+        let mut scheduler = Scheduler::new();
+        scheduler.set_lane(NETWORK_LANE.into(), 1).unwrap();
+        assert_blocked_commands_are_skipped(
+            scheduler,
+            ResourceRequest {
+                lane: Some(NETWORK_LANE.into()),
+                ..Default::default()
+            },
+            Default::default(),
+        )
+        .await;
+    }
+
+    /// Test that commands waiting for a held lock do not block other commands
+    #[tokio::test]
+    #[serial]
+    async fn lock_does_not_block_other_commands() {
+        assert_blocked_commands_are_skipped(
+            Scheduler::new(),
+            ResourceRequest {
+                locks: vec!["db".into()],
+                ..Default::default()
+            },
+            ResourceRequest {
+                lane: Some(NETWORK_LANE.into()),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    /// Test that commands with undeclared inputs can read them and are not cached
+    #[tokio::test]
+    #[serial]
+    async fn undeclared_inputs() {
+        for _ in 0..2 {
+            let mut scheduler = Scheduler::new();
+            let mut builder = CommandBuilder::new("cat".into(), vec!["test/data/a.csv".into()]);
+            builder
+                .custom_command_executor("cat".into(), Default::default(), &mut scheduler)
+                .unwrap();
+            builder.undeclared_inputs();
+            scheduler.push(builder).unwrap();
+            let stats = scheduler.run().await.unwrap();
+            assert_eq!(stats.exec.succeeded, 1);
+            assert_eq!(stats.cache_hits, 0);
+        }
+    }
+
+    /// Test that a long blocking task does not delay handling the completion of other commands
+    #[tokio::test]
+    #[serial]
+    async fn long_task_does_not_block_scheduler() {
+        let long_task_duration = Duration::from_millis(1500);
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        let mut builder = CommandBuilder::new("long-task".into(), vec![]);
+        builder.task_executor(Arc::new(move || {
+            std::thread::sleep(long_task_duration);
+            Ok(())
+        }));
+        scheduler.push(builder).unwrap();
+        // in the network lane to not wait for the CPU slot of the long task on a single CPU machine
+        let network = ResourceRequest {
+            lane: Some(NETWORK_LANE.into()),
+            ..Default::default()
+        };
+        let builder = sleep_command(
+            &mut scheduler,
+            "short",
+            0.1,
+            None,
+            Some("short.txt"),
+            network.clone(),
+        );
+        scheduler.push(builder).unwrap();
+        let dependent_finished = Arc::new(std::sync::Mutex::new(None));
+        let mut builder = CommandBuilder::new("dependent".into(), vec![]);
+        builder.input(&"short.txt".into(), &mut scheduler).unwrap();
+        let finished = dependent_finished.clone();
+        builder.async_task_executor(Arc::new(move || {
+            let finished = finished.clone();
+            Box::pin(async move {
+                *finished.lock().unwrap() = Some(Instant::now());
+                Ok(())
+            })
+        }));
+        builder.resources(network);
+        scheduler.push(builder).unwrap();
+        let start = Instant::now();
+        let stats = scheduler.run().await.unwrap();
+        assert_eq!(stats.exec.succeeded, 3);
+        assert!(start.elapsed() >= long_task_duration);
+        let dependent_finished = dependent_finished.lock().unwrap().unwrap();
+        assert!(dependent_finished - start < long_task_duration / 2);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn async_task_with_dependent() {
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        let mut builder = CommandBuilder::new("async-task".into(), vec![]);
+        let output = builder
+            .output(&"async-task.txt".into(), &mut scheduler)
+            .unwrap();
+        builder.async_task_executor(Arc::new(move || {
+            let output = output.clone();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                tokio::fs::write(output, "async").await?;
+                Ok(())
+            })
+        }));
+        scheduler.push(builder).unwrap();
+        let mut builder = CommandBuilder::new("dependent".into(), vec!["async-task.txt".into()]);
+        builder
+            .input(&"async-task.txt".into(), &mut scheduler)
+            .unwrap();
+        builder
+            .custom_command_executor("cat".into(), Default::default(), &mut scheduler)
+            .unwrap();
+        scheduler.push(builder).unwrap();
+        let stats = scheduler.run().await.unwrap();
+        assert_eq!(
+            stats.exec,
+            SchedulerExecStats {
+                succeeded: 2,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn hardlink_strategy_does_not_modify_inputs() {
+        let input = "target/hardlink-input.txt";
+        std::fs::create_dir_all("target").unwrap();
+        std::fs::write(input, "source\n").unwrap();
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        let mut builder = CommandBuilder::new(
+            "write-input".into(),
+            vec![
+                "-c".into(),
+                "echo modified >> $0 && cp $0 $1".into(),
+                input.into(),
+                "hardlink-output.txt".into(),
+            ],
+        );
+        builder.input(&input.into(), &mut scheduler).unwrap();
+        let output = builder
+            .output(&"hardlink-output.txt".into(), &mut scheduler)
+            .unwrap();
+        builder
+            .custom_command_executor("sh".into(), Default::default(), &mut scheduler)
+            .unwrap();
+        builder.sandbox_strategy(Some(SandboxStrategy::Hardlink));
+        scheduler.push(builder).unwrap();
+        let stats = scheduler.run().await.unwrap();
+        assert_eq!(stats.exec.succeeded, 1);
+        assert_eq!(std::fs::read_to_string(input).unwrap(), "source\n");
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "source\nmodified\n"
+        );
+        // the output is a symlink into the cache, where blobs are read-only
+        assert!(std::fs::metadata(&output).unwrap().permissions().readonly());
+    }
 }
@@ -0,0 +1,332 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Word or operator of a shell command line
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShellToken {
+    /// Argument with quotes and escapes removed
+    Word(String),
+    /// Control or redirection operator, e.g. `|`, `&&`, `>`, `2>`
+    Operator(String),
+}
+
+/// Tokens of a command line, which might span multiple lines using `\` continuations
+#[derive(Debug, PartialEq)]
+pub struct ShellLine {
+    /// first line of the command, starting at 1
+    pub line: usize,
+    pub tokens: Vec<ShellToken>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ShellWordsError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ShellWordsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ShellWordsError {}
+
+/// Split a shell script into command lines of words and operators like POSIX sh.
+///
+/// Supports single/double quotes, backslash escapes, line continuations and comments.
+/// Expansions like `$VAR`, `$(...)` and backticks are rejected, because commands are not run by a shell.
+pub fn split_shell_lines(text: &str) -> Result<Vec<ShellLine>, ShellWordsError> {
+    Lexer {
+        chars: text.chars().peekable(),
+        line: 1,
+        lines: vec![],
+        tokens: vec![],
+        command_line: 1,
+        word: None,
+    }
+    .run()
+}
+
+static OPERATOR_CHARS: &str = "|&;<>()";
+
+/// Operators consisting of two chars, all others are single chars
+static TWO_CHAR_OPERATORS: [&str; 3] = ["||", "&&", ">>"];
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// current line
+    line: usize,
+    lines: Vec<ShellLine>,
+    tokens: Vec<ShellToken>,
+    /// first line of the current command
+    command_line: usize,
+    /// current word, Some("") for an empty quoted word
+    word: Option<String>,
+}
+
+impl<'a> Lexer<'a> {
+    fn run(mut self) -> Result<Vec<ShellLine>, ShellWordsError> {
+        while let Some(c) = self.chars.next() {
+            match c {
+                ' ' | '\t' | '\r' => self.finish_word(),
+                '\n' => {
+                    self.finish_line();
+                    self.line += 1;
+                }
+                '#' if self.word.is_none() => while self.chars.next_if(|x| *x != '\n').is_some() {},
+                '\\' => match self.chars.next() {
+                    Some('\n') => self.line += 1,
+                    Some(x) => self.push(x),
+                    None => self.push('\\'),
+                },
+                '\'' => self.single_quoted()?,
+                '"' => self.double_quoted()?,
+                '$' => self.dollar()?,
+                '`' => {
+                    return Err(self.error("command substitution with backticks is not supported"))
+                }
+                _ if OPERATOR_CHARS.contains(c) => self.operator(c),
+                _ => self.push(c),
+            }
+        }
+        self.finish_line();
+        Ok(self.lines)
+    }
+
+    fn push(&mut self, c: char) {
+        if self.word.is_none() && self.tokens.is_empty() {
+            self.command_line = self.line;
+        }
+        self.word.get_or_insert_with(String::new).push(c);
+    }
+
+    fn start_word(&mut self) {
+        if self.word.is_none() && self.tokens.is_empty() {
+            self.command_line = self.line;
+        }
+        self.word.get_or_insert_with(String::new);
+    }
+
+    fn finish_word(&mut self) {
+        if let Some(word) = self.word.take() {
+            self.tokens.push(ShellToken::Word(word));
+        }
+    }
+
+    fn finish_line(&mut self) {
+        self.finish_word();
+        if !self.tokens.is_empty() {
+            self.lines.push(ShellLine {
+                line: self.command_line,
+                tokens: std::mem::take(&mut self.tokens),
+            });
+        }
+    }
+
+    fn single_quoted(&mut self) -> Result<(), ShellWordsError> {
+        let start_line = self.line;
+        self.start_word();
+        loop {
+            match self.chars.next() {
+                Some('\'') => return Ok(()),
+                Some(c) => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    self.push(c);
+                }
+                None => {
+                    return Err(ShellWordsError {
+                        line: start_line,
+                        message: "unterminated single quote".into(),
+                    })
+                }
+            }
+        }
+    }
+
+    fn double_quoted(&mut self) -> Result<(), ShellWordsError> {
+        let start_line = self.line;
+        self.start_word();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(()),
+                Some('\\') => match self.chars.next_if(|x| "$`\"\\\n".contains(*x)) {
+                    Some('\n') => self.line += 1,
+                    Some(c) => self.push(c),
+                    None => self.push('\\'),
+                },
+                Some('$') => self.dollar()?,
+                Some('`') => {
+                    return Err(self.error("command substitution with backticks is not supported"))
+                }
+                Some(c) => {
+                    if c == '\n' {
+                        self.line += 1;
+                    }
+                    self.push(c);
+                }
+                None => {
+                    return Err(ShellWordsError {
+                        line: start_line,
+                        message: "unterminated double quote".into(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// `$` is kept if it does not start an expansion
+    fn dollar(&mut self) -> Result<(), ShellWordsError> {
+        match self.chars.peek().cloned() {
+            Some('(') => Err(self.error("command substitution `$(...)` is not supported")),
+            Some('{') => Err(self.error("variable expansion `${...}` is not supported")),
+            Some(c) if c == '_' || c.is_ascii_alphanumeric() => {
+                let mut name = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|x| *x == '_' || x.is_ascii_alphanumeric())
+                {
+                    name.push(c);
+                }
+                Err(self.error(&format!("variable expansion `${name}` is not supported")))
+            }
+            Some(c) if "@*#?$!-".contains(c) => {
+                Err(self.error(&format!("special parameter `${c}` is not supported")))
+            }
+            _ => {
+                self.push('$');
+                Ok(())
+            }
+        }
+    }
+
+    fn operator(&mut self, c: char) {
+        let mut operator = String::new();
+        // file descriptor number directly before a redirection, e.g. `2>`
+        if let Some(word) = self.word.take() {
+            if (c == '<' || c == '>')
+                && !word.is_empty()
+                && word.chars().all(|x| x.is_ascii_digit())
+            {
+                operator = word;
+            } else {
+                self.tokens.push(ShellToken::Word(word));
+            }
+        }
+        if self.tokens.is_empty() && operator.is_empty() {
+            self.command_line = self.line;
+        }
+        operator.push(c);
+        if let Some(next) = self.chars.peek() {
+            let two = format!("{c}{next}");
+            if TWO_CHAR_OPERATORS.contains(&two.as_str()) {
+                operator.push(*next);
+                self.chars.next();
+            }
+        }
+        self.tokens.push(ShellToken::Operator(operator));
+    }
+
+    fn error(&self, message: &str) -> ShellWordsError {
+        ShellWordsError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<String> {
+        let lines = split_shell_lines(text).unwrap();
+        assert_eq!(lines.len(), 1);
+        lines[0]
+            .tokens
+            .iter()
+            .map(|x| match x {
+                ShellToken::Word(x) => x.clone(),
+                ShellToken::Operator(x) => panic!("unexpected operator: {x}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(words("a  b\tc"), ["a", "b", "c"]);
+        assert_eq!(
+            words(r#"echo "a b" 'c d' e\ f"#),
+            ["echo", "a b", "c d", "e f"]
+        );
+        assert_eq!(words(r#"a"b"'c' "" ''"#), ["abc", "", ""]);
+        assert_eq!(words(r#""\"\\\$\a" '\n'"#), ["\"\\$\\a", "\\n"]);
+        assert_eq!(words("a b# c # comment"), ["a", "b#", "c"]);
+        assert_eq!(words("a $ b$"), ["a", "$", "b$"]);
+    }
+
+    #[test]
+    fn lines() {
+        let lines = split_shell_lines("# comment\n\na \\\n  b\nc 'd\ne'\n\nf").unwrap();
+        let exp = |line, words: &[&str]| ShellLine {
+            line,
+            tokens: words
+                .iter()
+                .map(|x| ShellToken::Word(x.to_string()))
+                .collect(),
+        };
+        assert_eq!(
+            lines,
+            [exp(3, &["a", "b"]), exp(5, &["c", "d\ne"]), exp(8, &["f"])]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let lines = split_shell_lines("a&&b|c 2>e >>f<g").unwrap();
+        let word = |x: &str| ShellToken::Word(x.into());
+        let op = |x: &str| ShellToken::Operator(x.into());
+        assert_eq!(
+            lines[0].tokens,
+            [
+                word("a"),
+                op("&&"),
+                word("b"),
+                op("|"),
+                word("c"),
+                op("2>"),
+                word("e"),
+                op(">>"),
+                word("f"),
+                op("<"),
+                word("g"),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text| split_shell_lines(text).unwrap_err();
+        assert_eq!(error("a\nb 'c").line, 2);
+        assert_eq!(error("a\nb 'c").message, "unterminated single quote");
+        assert_eq!(error("a \"b\n").message, "unterminated double quote");
+        assert_eq!(
+            error("\n\necho $(pwd)"),
+            ShellWordsError {
+                line: 3,
+                message: "command substitution `$(...)` is not supported".into()
+            }
+        );
+        assert_eq!(
+            error("echo \"$HOME\"").message,
+            "variable expansion `$HOME` is not supported"
+        );
+        assert_eq!(
+            error("echo `pwd`").message,
+            "command substitution with backticks is not supported"
+        );
+    }
+}
@@ -1,7 +1,10 @@
-use anyhow::ensure;
+use anyhow::{bail, ensure, Context};
 use csv::{StringRecord, Writer};
+use itertools::Itertools;
+use regex::Regex;
 use std::io;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 pub fn csv_concat(inputs: Vec<PathBuf>, output: PathBuf) -> Result<(), anyhow::Error> {
     let mut writer = csv::Writer::from_path(output)?;
@@ -28,13 +31,28 @@ pub fn csv_filter(
     input: PathBuf,
     output: PathBuf,
     cols: Vec<String>,
-    fields: Vec<(String, String)>,
+    fields: Vec<CsvFieldFilter>,
 ) -> Result<(), anyhow::Error> {
-    if !fields.is_empty() {
-        todo!();
-    }
-    let mut reader = csv::Reader::from_path(input)?;
-    let headers = reader.headers()?;
+    let mut reader = csv::Reader::from_path(&input)?;
+    let headers = reader.headers()?.clone();
+    let field_filters = fields
+        .iter()
+        .map(|filter| {
+            headers
+                .iter()
+                .position(|x| x == filter.field)
+                .map(|i| (i, filter))
+                .with_context(|| {
+                    format!(
+                        "field not found in {input:?}: {:?}, available: {}",
+                        filter.field,
+                        headers.iter().join(", ")
+                    )
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .into_group_map();
     let indices: Vec<usize> = if !cols.is_empty() {
         headers
             .iter()
@@ -45,10 +63,15 @@ pub fn csv_filter(
         headers.iter().enumerate().map(|(i, _)| i).collect()
     };
     let mut writer = csv::Writer::from_path(output)?;
-    write_record_filtered(&mut writer, headers, &indices)?;
+    write_record_filtered(&mut writer, &headers, &indices)?;
     for result in reader.records() {
-        // TODO filter fields
-        write_record_filtered(&mut writer, &result?, &indices)?;
+        let record = result?;
+        let keep = field_filters.iter().all(|(i, filters)| {
+            CsvFieldFilter::matches_all(filters, record.get(*i).unwrap_or_default())
+        });
+        if keep {
+            write_record_filtered(&mut writer, &record, &indices)?;
+        }
     }
     writer.flush()?;
     Ok(())
@@ -65,3 +88,155 @@ fn write_record_filtered<W: io::Write>(
     writer.write_record(None::<&[u8]>)?;
     Ok(())
 }
+
+/// Condition on a field of csv_filter: `a=1`, `a!=1`, `a~regex`, `a!~regex`, `a<1`, `a<=1`, `a>1`, `a>=1`
+#[derive(Clone, Debug)]
+pub struct CsvFieldFilter {
+    pub field: String,
+    condition: CsvFieldCondition,
+}
+
+#[derive(Clone, Debug)]
+enum CsvFieldCondition {
+    Equal(String),
+    NotEqual(String),
+    Match(Regex),
+    NotMatch(Regex),
+    Less(f64),
+    LessEqual(f64),
+    Greater(f64),
+    GreaterEqual(f64),
+}
+
+impl CsvFieldFilter {
+    /// Values given with `=` and `~` are alternatives, all other conditions must be true
+    fn matches_all(filters: &[&CsvFieldFilter], value: &str) -> bool {
+        let (alternatives, conditions): (Vec<&&CsvFieldFilter>, Vec<_>) =
+            filters.iter().partition(|x| x.is_alternative());
+        (alternatives.is_empty() || alternatives.iter().any(|x| x.matches(value)))
+            && conditions.iter().all(|x| x.matches(value))
+    }
+
+    fn is_alternative(&self) -> bool {
+        matches!(
+            self.condition,
+            CsvFieldCondition::Equal(_) | CsvFieldCondition::Match(_)
+        )
+    }
+
+    /// Numeric comparisons do not match values which are not numbers
+    fn matches(&self, value: &str) -> bool {
+        let number = || value.trim().parse::<f64>().ok();
+        match &self.condition {
+            CsvFieldCondition::Equal(x) => value == x,
+            CsvFieldCondition::NotEqual(x) => value != x,
+            CsvFieldCondition::Match(x) => x.is_match(value),
+            CsvFieldCondition::NotMatch(x) => !x.is_match(value),
+            CsvFieldCondition::Less(x) => number().is_some_and(|v| v < *x),
+            CsvFieldCondition::LessEqual(x) => number().is_some_and(|v| v <= *x),
+            CsvFieldCondition::Greater(x) => number().is_some_and(|v| v > *x),
+            CsvFieldCondition::GreaterEqual(x) => number().is_some_and(|v| v >= *x),
+        }
+    }
+}
+
+impl FromStr for CsvFieldFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pos = s
+            .find(['=', '!', '~', '<', '>'])
+            .with_context(|| format!("invalid field filter, no operator found: {s}"))?;
+        let (field, rest) = s.split_at(pos);
+        if field.is_empty() {
+            bail!("invalid field filter, field name missing: {s}");
+        }
+        let number = |x: &str| {
+            x.trim()
+                .parse::<f64>()
+                .with_context(|| format!("invalid field filter, not a number: {s}"))
+        };
+        let regex =
+            |x: &str| Regex::new(x).with_context(|| format!("invalid field filter regex: {s}"));
+        let condition = if let Some(x) = rest.strip_prefix("!=") {
+            CsvFieldCondition::NotEqual(x.into())
+        } else if let Some(x) = rest.strip_prefix("!~") {
+            CsvFieldCondition::NotMatch(regex(x)?)
+        } else if let Some(x) = rest.strip_prefix("<=") {
+            CsvFieldCondition::LessEqual(number(x)?)
+        } else if let Some(x) = rest.strip_prefix(">=") {
+            CsvFieldCondition::GreaterEqual(number(x)?)
+        } else if let Some(x) = rest.strip_prefix('=') {
+            CsvFieldCondition::Equal(x.into())
+        } else if let Some(x) = rest.strip_prefix('~') {
+            CsvFieldCondition::Match(regex(x)?)
+        } else if let Some(x) = rest.strip_prefix('<') {
+            CsvFieldCondition::Less(number(x)?)
+        } else if let Some(x) = rest.strip_prefix('>') {
+            CsvFieldCondition::Greater(number(x)?)
+        } else {
+            bail!("invalid field filter, unknown operator: {s}");
+        };
+        Ok(Self {
+            field: field.into(),
+            condition,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    fn filter(input: &str, fields: &[&str]) -> Result<String, anyhow::Error> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.child("input.csv"), input)?;
+        let fields = fields
+            .iter()
+            .map(|x| x.parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        csv_filter(
+            dir.child("input.csv"),
+            dir.child("output.csv"),
+            vec![],
+            fields,
+        )?;
+        Ok(std::fs::read_to_string(dir.child("output.csv"))?)
+    }
+
+    #[test]
+    fn fields() {
+        let input = "name,kind,size\na,x,1\nb,y,2.5\nc,x,10\nd,z,\n";
+        let rows = |fields: &[&str]| {
+            filter(input, fields)
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|x| x.split(',').next().unwrap().to_string())
+                .collect_vec()
+        };
+        assert_eq!(rows(&[]), ["a", "b", "c", "d"]);
+        assert_eq!(rows(&["kind=x"]), ["a", "c"]);
+        assert_eq!(rows(&["kind=x", "kind=z"]), ["a", "c", "d"]);
+        assert_eq!(rows(&["kind=x", "size>1"]), ["c"]);
+        assert_eq!(rows(&["kind!=x"]), ["b", "d"]);
+        assert_eq!(rows(&["kind!=x", "kind!=y"]), ["d"]);
+        assert_eq!(rows(&["name~^[ab]$"]), ["a", "b"]);
+        assert_eq!(rows(&["name!~[ab]"]), ["c", "d"]);
+        assert_eq!(rows(&["size>=2.5", "size<10"]), ["b"]);
+        assert_eq!(rows(&["size<=1"]), ["a"]);
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let error = filter("a,b\n1,2\n", &["c=1"]).unwrap_err().to_string();
+        assert!(error.contains("field not found"), "{error}");
+        assert!(error.contains("available: a, b"), "{error}");
+        assert!("a".parse::<CsvFieldFilter>().is_err());
+        assert!("=1".parse::<CsvFieldFilter>().is_err());
+        assert!("a<x".parse::<CsvFieldFilter>().is_err());
+        assert!("a~(".parse::<CsvFieldFilter>().is_err());
+    }
+}
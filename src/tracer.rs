@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::ExitStatus;
+use std::ptr::null_mut;
+
+use crate::FileAccess;
+
+/// Execute a command and trace the files opened by it and all its child processes using ptrace.
+///
+/// Must be called from a blocking thread: the thread which spawns the command becomes the tracer.
+/// Returns the resource usage of the command and its waited-for children.
+pub fn trace(
+    mut command: std::process::Command,
+) -> io::Result<(ExitStatus, libc::rusage, Vec<FileAccess>)> {
+    unsafe {
+        command.pre_exec(|| {
+            let null = null_mut::<libc::c_void>();
+            check(libc::ptrace(libc::PTRACE_TRACEME, 0, null, null))
+        });
+    }
+    let child = command.spawn()?;
+    let main_pid = child.id() as libc::pid_t;
+    let mut accesses = vec![];
+    let mut exit_status = None;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    // known tracees and if they are currently within a syscall
+    let mut in_syscall: HashMap<libc::pid_t, bool> = Default::default();
+    loop {
+        let mut status = 0;
+        let mut pid_rusage = unsafe { std::mem::zeroed() };
+        // __WNOTHREAD: do not reap children of other threads
+        let pid = unsafe {
+            libc::wait4(
+                -1,
+                &mut status,
+                libc::__WALL | libc::__WNOTHREAD,
+                &mut pid_rusage,
+            )
+        };
+        if pid < 0 {
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ECHILD) => break,
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                e => return Err(e),
+            }
+        }
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            in_syscall.remove(&pid);
+            if pid == main_pid {
+                exit_status = Some(ExitStatus::from_raw(status));
+                rusage = pid_rusage;
+            }
+            continue;
+        }
+        if !libc::WIFSTOPPED(status) {
+            continue;
+        }
+        let signal = libc::WSTOPSIG(status);
+        let mut inject_signal = 0;
+        if pid == main_pid && !in_syscall.contains_key(&pid) {
+            // initial SIGTRAP after execve()
+            in_syscall.insert(pid, false);
+            let options = libc::PTRACE_O_TRACESYSGOOD
+                | libc::PTRACE_O_TRACEFORK
+                | libc::PTRACE_O_TRACEVFORK
+                | libc::PTRACE_O_TRACECLONE
+                | libc::PTRACE_O_TRACEEXEC
+                | libc::PTRACE_O_EXITKILL;
+            let null = null_mut::<libc::c_void>();
+            check(unsafe {
+                libc::ptrace(libc::PTRACE_SETOPTIONS, pid, null, options as libc::c_long)
+            })?;
+        } else if signal == libc::SIGTRAP | 0x80 {
+            let entered = in_syscall.entry(pid).or_default();
+            *entered = !*entered;
+            if !*entered {
+                accesses.extend(syscall_exit(pid));
+            }
+        } else if status >> 16 != 0 {
+            // ptrace event stop: fork, clone, exec - new tracees are attached automatically
+        } else if signal == libc::SIGSTOP && !in_syscall.contains_key(&pid) {
+            // initial stop of a new tracee
+            in_syscall.insert(pid, false);
+        } else {
+            inject_signal = signal;
+        }
+        let null = null_mut::<libc::c_void>();
+        unsafe {
+            libc::ptrace(
+                libc::PTRACE_SYSCALL,
+                pid,
+                null,
+                inject_signal as libc::c_long,
+            )
+        };
+    }
+    let exit_status =
+        exit_status.ok_or_else(|| io::Error::other("traced command was not reaped"))?;
+    Ok((exit_status, rusage, accesses))
+}
+
+fn check(result: libc::c_long) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Check the result of a syscall and return the opened/executed file
+fn syscall_exit(pid: libc::pid_t) -> Option<FileAccess> {
+    let (nr, ret) = syscall_nr_and_ret(pid)?;
+    if ret < 0 {
+        None
+    } else if is_open_syscall(nr) {
+        let path = std::fs::read_link(format!("/proc/{pid}/fd/{ret}")).ok()?;
+        let fdinfo = std::fs::read_to_string(format!("/proc/{pid}/fdinfo/{ret}")).ok()?;
+        let flags = fdinfo
+            .lines()
+            .find_map(|x| x.strip_prefix("flags:"))
+            .and_then(|x| i32::from_str_radix(x.trim(), 8).ok())?;
+        Some(FileAccess {
+            path,
+            write: flags & libc::O_ACCMODE != libc::O_RDONLY,
+        })
+    } else if nr == libc::SYS_execve || nr == libc::SYS_execveat {
+        let path = std::fs::read_link(format!("/proc/{pid}/exe")).ok()?;
+        Some(FileAccess { path, write: false })
+    } else {
+        None
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn is_open_syscall(nr: libc::c_long) -> bool {
+    [
+        libc::SYS_open,
+        libc::SYS_creat,
+        libc::SYS_openat,
+        libc::SYS_openat2,
+    ]
+    .contains(&nr)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn is_open_syscall(nr: libc::c_long) -> bool {
+    [libc::SYS_openat, libc::SYS_openat2].contains(&nr)
+}
+
+fn get_regs(pid: libc::pid_t) -> Option<libc::user_regs_struct> {
+    let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+    let mut iovec = libc::iovec {
+        iov_base: &mut regs as *mut _ as *mut libc::c_void,
+        iov_len: std::mem::size_of::<libc::user_regs_struct>(),
+    };
+    let result = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETREGSET,
+            pid,
+            libc::NT_PRSTATUS as libc::c_long,
+            &mut iovec as *mut _ as *mut libc::c_void,
+        )
+    };
+    (result == 0).then_some(regs)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn syscall_nr_and_ret(pid: libc::pid_t) -> Option<(libc::c_long, libc::c_long)> {
+    get_regs(pid).map(|x| (x.orig_rax as libc::c_long, x.rax as libc::c_long))
+}
+
+#[cfg(target_arch = "aarch64")]
+fn syscall_nr_and_ret(pid: libc::pid_t) -> Option<(libc::c_long, libc::c_long)> {
+    get_regs(pid).map(|x| (x.regs[8] as libc::c_long, x.regs[0] as libc::c_long))
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use crate::{Scheduler, SchedulerExecStats};
+
+    async fn run_detect_undeclared(
+        args: Vec<String>,
+        inputs: Vec<String>,
+        outputs: Vec<String>,
+    ) -> SchedulerExecStats {
+        let mut scheduler = Scheduler::new();
+        scheduler.read_cache = false;
+        scheduler.sandbox_config.detect_undeclared = true;
+        scheduler
+            .push_custom_command(
+                "test".into(),
+                "cmake".into(),
+                args,
+                Default::default(),
+                inputs,
+                outputs,
+            )
+            .unwrap();
+        scheduler.run().await.unwrap().exec
+    }
+
+    fn a_csv_abs() -> String {
+        let cwd = std::env::current_dir().unwrap();
+        cwd.join("test/data/a.csv").to_str().unwrap().into()
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn all_declared() {
+        let args = vec!["-E".into(), "copy".into(), a_csv_abs(), "tracer.csv".into()];
+        let stats = run_detect_undeclared(args, vec![a_csv_abs()], vec!["tracer.csv".into()]).await;
+        assert_eq!(stats.succeeded, 1);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn undeclared_input() {
+        let args = vec!["-E".into(), "copy".into(), a_csv_abs(), "tracer.csv".into()];
+        let stats = run_detect_undeclared(args, vec![], vec!["tracer.csv".into()]).await;
+        assert_eq!(stats.failed, 1);
+    }
+
+    /// Reads via `../` from the sandbox dir, i.e. from the dir of all sandboxes of the process
+    #[tokio::test]
+    #[serial]
+    async fn undeclared_input_relative_to_sandbox() {
+        let dir = Scheduler::new().sandbox_config.process_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("x");
+        std::fs::write(&file, "x").unwrap();
+        let args = vec![
+            "-E".into(),
+            "copy".into(),
+            "../x".into(),
+            "tracer.csv".into(),
+        ];
+        let stats = run_detect_undeclared(args, vec![], vec!["tracer.csv".into()]).await;
+        std::fs::remove_file(file).unwrap();
+        assert_eq!(stats.failed, 1);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn undeclared_input_outside_workspace() {
+        let cwd = std::env::current_dir().unwrap();
+        let file = cwd.parent().unwrap().join("razel-tracer-test.csv");
+        std::fs::write(&file, "x").unwrap();
+        let file_str = file.to_str().unwrap().to_string();
+        let args = vec!["-E".into(), "copy".into(), file_str, "tracer.csv".into()];
+        let stats = run_detect_undeclared(args, vec![], vec!["tracer.csv".into()]).await;
+        std::fs::remove_file(file).unwrap();
+        assert_eq!(stats.failed, 1);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn undeclared_output() {
+        let args = vec!["-E".into(), "copy".into(), a_csv_abs(), "tracer.csv".into()];
+        let stats = run_detect_undeclared(args, vec![a_csv_abs()], vec![]).await;
+        assert_eq!(stats.failed, 1);
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::Context;
+use log::debug;
+use tokio::task::spawn_blocking;
+
+/// Force creating a hardlink: overwrite existing file and create parent directories
+///
+/// Falls back to copying the file if hardlinking fails, e.g. across devices.
+pub async fn force_hardlink(src: &Path, dst: &Path) -> Result<(), anyhow::Error> {
+    assert_ne!(src, dst);
+    {
+        let src = src.to_path_buf();
+        let dst = dst.to_path_buf();
+        spawn_blocking(move || {
+            prepare_dst(&dst)?;
+            fs::hard_link(&src, &dst).or_else(|e| {
+                debug!(
+                    "hardlink {:?} -> {:?} failed, fallback to copy: {}",
+                    src, dst, e
+                );
+                copy_file(&src, &dst)
+            })
+        })
+        .await?
+    }
+    .with_context(|| format!("hardlink {:?} -> {:?}", src, dst))?;
+    Ok(())
+}
+
+/// Force copying a file: overwrite existing file and create parent directories
+///
+/// A reflink is created if supported by the file system.
+pub async fn force_copy(src: &Path, dst: &Path) -> Result<(), anyhow::Error> {
+    assert_ne!(src, dst);
+    {
+        let src = src.to_path_buf();
+        let dst = dst.to_path_buf();
+        spawn_blocking(move || {
+            prepare_dst(&dst)?;
+            copy_file(&src, &dst)
+        })
+        .await?
+    }
+    .with_context(|| format!("copy {:?} -> {:?}", src, dst))?;
+    Ok(())
+}
+
+fn prepare_dst(dst: &Path) -> io::Result<()> {
+    fs::remove_file(dst).ok();
+    fs::create_dir_all(dst.parent().unwrap())
+}
+
+#[cfg(target_os = "linux")]
+fn copy_file(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    const FICLONE: libc::c_ulong = 0x40049409;
+    let src_file = fs::File::open(src)?;
+    let dst_file = fs::File::create(dst)?;
+    if unsafe { libc::ioctl(dst_file.as_raw_fd(), FICLONE, src_file.as_raw_fd()) } == 0 {
+        return dst_file.set_permissions(src_file.metadata()?.permissions());
+    }
+    drop(dst_file);
+    fs::copy(src, dst).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_file(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::copy(src, dst).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    const CONTENT: &str = "CONTENT";
+    const OTHER_CONTENT: &str = "OTHER_CONTENT";
+
+    #[tokio::test]
+    async fn hardlink_shares_content() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.child("src-file");
+        let dst = dir.child("dst-dir").join("dst-file");
+        fs::write(&src, CONTENT).unwrap();
+        force_hardlink(&src, &dst).await.unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), CONTENT);
+        assert!(!dst.is_symlink());
+        fs::write(&dst, OTHER_CONTENT).unwrap();
+        assert_eq!(fs::read_to_string(&src).unwrap(), OTHER_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn copy_is_independent() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.child("src-file");
+        let dst = dir.child("dst-dir").join("dst-file");
+        fs::write(&src, CONTENT).unwrap();
+        force_copy(&src, &dst).await.unwrap();
+        force_copy(&src, &dst).await.unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), CONTENT);
+        fs::write(&dst, OTHER_CONTENT).unwrap();
+        assert_eq!(fs::read_to_string(&src).unwrap(), CONTENT);
+    }
+}
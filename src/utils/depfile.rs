@@ -0,0 +1,93 @@
+/// Options of gcc/clang to write dependency files, removed because razel tracks header dependencies
+static DEPFILE_OPTIONS: [&str; 3] = ["-MD", "-MMD", "-MP"];
+static DEPFILE_OPTIONS_WITH_VALUE: [&str; 3] = ["-MF", "-MT", "-MQ"];
+
+/// Remove options of gcc/clang which write a depfile while compiling
+pub fn remove_depfile_options(args: Vec<String>) -> Vec<String> {
+    let mut result = vec![];
+    let mut i = args.into_iter();
+    while let Some(arg) = i.next() {
+        if DEPFILE_OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+            i.next();
+        } else if !DEPFILE_OPTIONS.contains(&arg.as_str()) {
+            result.push(arg);
+        }
+    }
+    result
+}
+
+/// Args of gcc/clang without executable to write the header dependencies into a depfile instead of compiling
+pub fn scan_args(args: &[String], deps_file: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut i = args.iter();
+    while let Some(arg) = i.next() {
+        if arg == "-o" {
+            i.next();
+        } else if !arg.starts_with("-o") || arg.starts_with("-obj") {
+            result.push(arg.clone());
+        }
+    }
+    result.extend(["-M".into(), "-MF".into(), deps_file.into()]);
+    result
+}
+
+/// Returns the dependencies of all targets of a Makefile style depfile
+pub fn parse_depfile(text: &str) -> Vec<String> {
+    let mut deps = vec![];
+    let mut word = String::new();
+    let mut chars = text.chars().peekable();
+    let mut finish_word = |word: &mut String| {
+        // targets end with a colon
+        if !word.is_empty() && !word.ends_with(':') && !deps.contains(word) {
+            deps.push(word.clone());
+        }
+        word.clear();
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\n') | Some('\r') => finish_word(&mut word),
+                Some(x @ (' ' | '#' | '\\')) => word.push(x),
+                Some(x) => {
+                    word.push('\\');
+                    word.push(x);
+                }
+                None => {}
+            },
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                word.push('$');
+            }
+            ' ' | '\t' | '\n' | '\r' => finish_word(&mut word),
+            _ => word.push(c),
+        }
+    }
+    finish_word(&mut word);
+    deps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depfile() {
+        let deps = parse_depfile("obj/a.o: a.c \\\n /usr/include/a\\ b.h $$x.h\nc.h:\n");
+        assert_eq!(deps, ["a.c", "/usr/include/a b.h", "$x.h"]);
+    }
+
+    #[test]
+    fn scan() {
+        let args = [
+            "-MD", "-MT", "a.o", "-MF", "a.o.d", "-O2", "-oa.o", "-c", "a.c",
+        ]
+        .map(String::from)
+        .to_vec();
+        let args = remove_depfile_options(args);
+        assert_eq!(args, ["-O2", "-oa.o", "-c", "a.c"]);
+        assert_eq!(
+            scan_args(&args, "a.o.d"),
+            ["-O2", "-c", "a.c", "-M", "-MF", "a.o.d"]
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Remove `.` and `..` components like ninja does for paths
+pub fn canonicalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|x| *x != "..") => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if path.starts_with('/') {
+        format!("/{joined}")
+    } else if joined.is_empty() {
+        ".".into()
+    } else {
+        joined
+    }
+}
+
+/// Path of a file relative to a dir, both relative to cwd, absolute paths are kept
+pub fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.into();
+    }
+    let mut path_components = path.components().peekable();
+    let mut dir_components = dir.components().peekable();
+    while path_components.peek().is_some() && path_components.peek() == dir_components.peek() {
+        path_components.next();
+        dir_components.next();
+    }
+    dir_components
+        .map(|_| Component::ParentDir)
+        .chain(path_components)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonicalize() {
+        assert_eq!(canonicalize_path("./a/../b//c/."), "b/c");
+        assert_eq!(canonicalize_path("../a/./b"), "../a/b");
+        assert_eq!(canonicalize_path("/a/../b"), "/b");
+    }
+
+    #[test]
+    fn relative() {
+        let relative = |path: &str, dir: &str| relative_path(Path::new(path), Path::new(dir));
+        assert_eq!(relative("a/b.c", ""), Path::new("a/b.c"));
+        assert_eq!(relative("a/b.c", "a"), Path::new("b.c"));
+        assert_eq!(
+            relative("razel-out/a/b.c", "a"),
+            Path::new("../razel-out/a/b.c")
+        );
+        assert_eq!(relative("/usr/bin/cc", "a"), Path::new("/usr/bin/cc"));
+    }
+}
@@ -6,7 +6,14 @@ razel task write-file b.csv a,b,xyz 3,4,56 7,8,9
 razel task csv-concat data/a.csv b.csv c.csv
 razel task csv-filter -i c.csv -o filtered.csv -c a xyz
 razel task ensure-equal filtered.csv data/f.csv
-# add command: copy a file  # TODO use "cmake -E copy", requires Rule to support subcommands
-cp data/a.csv d.csv
-cp d.csv e.csv
+razel task csv-filter -i c.csv -o filtered-rows.csv -f 'a>=3' 'xyz~^5'
+razel task ensure-equal filtered-rows.csv data/filtered-rows.csv
+# add command: use cmake to copy a file
+cmake -E copy data/a.csv d.csv
+cmake -E copy d.csv e.csv
 razel task ensure-equal e.csv data/a.csv
+# redirect stdin/stdout and pipe commands
+cat < d.csv | cat > g.csv
+razel task ensure-equal g.csv data/a.csv
+# input file detected by a rule of razel.rules
+head -n 1 d.csv > h.csv
@@ -0,0 +1,4 @@
+#include "hello.h"
+#include "message.h"
+
+const char *hello(void) { return MESSAGE; }
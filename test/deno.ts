@@ -20,5 +20,10 @@ const d = razel.addCommand('d.csv', 'cmake', ['-E', 'copy', a, razel.addOutputFi
 razel.addCommand('e.csv', 'cmake', ['-E', 'copy', d, razel.addOutputFile('e.csv')])
     .output
     .ensureEqual(a);
+// add command which is expected to exit with code 1
+razel.addCommand('false', 'cmake', ['-E', 'false']).setSuccessCriteria({expected_exit_codes: [1]});
+// add command which gets args in a response file
+razel.addCommand('rsp.txt', 'sh', ['-c', 'cp "${1#@}" "$0"', razel.addOutputFile('rsp.txt'), a, 'x y'])
+    .useResponseFile(3);
 
 razel.writeRazelFile();